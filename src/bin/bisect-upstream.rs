@@ -0,0 +1,57 @@
+#[derive(structopt::StructOpt)]
+struct Opt {
+    /// Package directory having upstream.yml with head-commit strategy
+    #[structopt(parse(from_os_str))]
+    package: std::path::PathBuf,
+    /// Local clone of the upstream repository
+    #[structopt(long, parse(from_os_str))]
+    git_dir: std::path::PathBuf,
+    /// Commits known to be good
+    #[structopt(long, required = true)]
+    good: Vec<String>,
+    /// Commit known to be bad
+    #[structopt(long)]
+    bad: String,
+    #[structopt(flatten)]
+    client: arch_wanko_cc::github::ClientOpt,
+}
+
+/// Rewrites PKGBUILD to the next commit to test between --good and --bad.
+/// Build and test the package, then run it again adding the commit to --good or passing it as --bad.
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    use structopt::StructOpt as _;
+
+    let opt = Opt::from_args();
+    let package = arch_wanko_cc::upstream::Package::load(&opt.package)?;
+    let repo = git2::Repository::open(&opt.git_dir)?;
+    let resolve = |rev: &str| -> Result<git2::Oid, git2::Error> {
+        Ok(repo.revparse_single(rev)?.peel_to_commit()?.id())
+    };
+    let good = opt
+        .good
+        .iter()
+        .map(|rev| resolve(rev))
+        .collect::<Result<Vec<_>, _>>()?;
+    let bad = resolve(&opt.bad)?;
+
+    match arch_wanko_cc::upstream::bisect(&repo, &good, bad)? {
+        arch_wanko_cc::upstream::Bisect::Found(oid) => {
+            println!("{} is the first bad commit", oid);
+        }
+        arch_wanko_cc::upstream::Bisect::Step { commit, remaining } => {
+            println!(
+                "Bisecting: {} commits left to test, testing {}",
+                remaining - 1,
+                commit
+            );
+            let client = opt.client.client()?;
+            let result = package
+                .update_to_local_commit(&client, &repo.find_commit(commit)?)
+                .await;
+            client.save_cache()?;
+            result?;
+        }
+    }
+    Ok(())
+}
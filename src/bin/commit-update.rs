@@ -1,138 +1,246 @@
-extern crate git2;
-
-fn main() {
-    let repo = git2::Repository::discover(".").expect("Failed to discover repository");
-    let change = find_modified_pkgbuild(&repo)
-        .unwrap()
-        .or_else(|| find_modified_submodule(&repo).unwrap());
-    match change {
-        Some(change) => {
-            let new_version = evaluate_pkgbuild(&change.new_pkgbuild).unwrap();
-            let message = if let Some(old_pkgbuild) = change.old_pkgbuild {
-                let old_version = evaluate_pkgbuild(&old_pkgbuild).unwrap();
-                format!(
-                    "Update {} {} -> {}",
-                    change.pkgname, old_version, new_version
-                )
-            } else {
-                format!("Add {} {}", change.pkgname, new_version)
-            };
+#[derive(structopt::StructOpt)]
+struct Opt {
+    /// Make one commit per package instead of a single commit listing every package
+    #[structopt(long)]
+    separate: bool,
+}
 
-            use std::os::unix::process::CommandExt;
-            let err = std::process::Command::new("git")
-                .arg("commit")
-                .arg("-m")
-                .arg(message)
-                .exec();
-            panic!("{:?}", err);
-        }
-        None => {
-            println!("No PKGBUILD is modified");
-            std::process::exit(1);
-        }
+fn main() -> Result<(), anyhow::Error> {
+    use structopt::StructOpt as _;
+
+    let opt = Opt::from_args();
+    let repo = git2::Repository::discover(".")?;
+    let staged = staged_deltas(&repo)?;
+    let changes = find_changes(&repo, &staged)?;
+    if changes.is_empty() {
+        println!("No PKGBUILD is modified");
+        std::process::exit(1);
+    }
+    let messages = changes
+        .iter()
+        .map(|change| change.message())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if opt.separate && changes.len() > 1 {
+        commit_separately(&repo, &staged, &changes, &messages)
+    } else {
+        let message = if messages.len() == 1 {
+            messages[0].clone()
+        } else {
+            format!(
+                "Update {} packages\n\n{}",
+                messages.len(),
+                messages.join("\n")
+            )
+        };
+
+        use std::os::unix::process::CommandExt as _;
+        let err = std::process::Command::new("git")
+            .arg("commit")
+            .arg("-m")
+            .arg(message)
+            .exec();
+        Err(err.into())
     }
 }
 
+/// A file staged in the index, which differs from HEAD.
+struct StagedDelta {
+    path: std::path::PathBuf,
+    status: git2::Delta,
+    old_id: git2::Oid,
+    new_id: git2::Oid,
+    new_mode: git2::FileMode,
+}
+
 struct PKGBUILDChange {
     new_pkgbuild: Vec<u8>,
     old_pkgbuild: Option<Vec<u8>>,
     pkgname: String,
+    /// Directory of the package or path of the submodule. Staged files under it belong to the
+    /// package.
+    path: std::path::PathBuf,
+    submodule: bool,
 }
 
-fn find_modified_pkgbuild(repo: &git2::Repository) -> Result<Option<PKGBUILDChange>, git2::Error> {
-    use std::os::unix::ffi::OsStrExt;
-
-    let mut new_pkgbuild_entry = None;
-
-    for entry in repo.index()?.iter() {
-        let path = entry.path.clone();
-        let path = std::path::Path::new(std::ffi::OsStr::from_bytes(&path));
-        let status = repo.status_file(path)?;
-        if status.is_index_modified() || status.is_index_new() {
-            if let Some(filename) = path.file_name() {
-                if filename == "PKGBUILD" {
-                    if new_pkgbuild_entry.is_none() {
-                        new_pkgbuild_entry = Some(entry);
-                    } else {
-                        panic!("Multiple PKGBUILDs are modified");
-                    }
-                }
-            }
+impl PKGBUILDChange {
+    fn message(&self) -> Result<String, anyhow::Error> {
+        use anyhow::Context as _;
+
+        let new_version = evaluate_pkgbuild(&self.new_pkgbuild)
+            .with_context(|| format!("Failed to evaluate new PKGBUILD of {}", self.pkgname))?;
+        if let Some(ref old_pkgbuild) = self.old_pkgbuild {
+            let old_version = evaluate_pkgbuild(old_pkgbuild)
+                .with_context(|| format!("Failed to evaluate old PKGBUILD of {}", self.pkgname))?;
+            Ok(format!(
+                "Update {} {} -> {}",
+                self.pkgname, old_version, new_version
+            ))
+        } else {
+            Ok(format!("Add {} {}", self.pkgname, new_version))
         }
     }
+}
 
-    let new_pkgbuild_entry = match new_pkgbuild_entry {
-        Some(entry) => entry,
-        None => return Ok(None),
-    };
-    let new_pkgbuild_blob = repo.find_blob(new_pkgbuild_entry.id)?;
-    let new_pkgbuild_content = new_pkgbuild_blob.content().to_vec();
-    let pkgbuild_path = std::path::Path::new(std::ffi::OsStr::from_bytes(&new_pkgbuild_entry.path));
-    let head_oid = repo.head()?.target().unwrap();
-    let head = repo.find_commit(head_oid)?;
-    let head_tree = head.tree()?;
-    let old_pkgbuild_content = match head_tree.get_path(pkgbuild_path) {
-        Ok(entry) => {
-            let blob = repo.find_blob(entry.id())?;
-            Some(blob.content().to_vec())
-        }
-        Err(_) => None,
+fn staged_deltas(repo: &git2::Repository) -> Result<Vec<StagedDelta>, git2::Error> {
+    let head_tree = match repo.head() {
+        Ok(head) => Some(head.peel_to_tree()?),
+        Err(ref e) if e.code() == git2::ErrorCode::UnbornBranch => None,
+        Err(e) => return Err(e),
     };
-
-    Ok(Some(PKGBUILDChange {
-        new_pkgbuild: new_pkgbuild_content,
-        old_pkgbuild: old_pkgbuild_content,
-        pkgname: path_to_pkgname(pkgbuild_path.parent().expect("Invalid PKGBUILD path")),
-    }))
+    let diff = repo.diff_tree_to_index(head_tree.as_ref(), None, None)?;
+    Ok(diff
+        .deltas()
+        .filter_map(|delta| {
+            let file = if delta.status() == git2::Delta::Deleted {
+                delta.old_file()
+            } else {
+                delta.new_file()
+            };
+            file.path().map(|path| StagedDelta {
+                path: path.to_owned(),
+                status: delta.status(),
+                old_id: delta.old_file().id(),
+                new_id: delta.new_file().id(),
+                new_mode: delta.new_file().mode(),
+            })
+        })
+        .collect())
 }
 
-fn find_modified_submodule(repo: &git2::Repository) -> Result<Option<PKGBUILDChange>, git2::Error> {
-    let mut modified_submodule = None;
-
-    for submodule in repo.submodules()? {
-        let status = repo.submodule_status(
-            submodule.name().expect(
-                "Invalid UTF-8 sequence is found \
-                 at submodule's name",
-            ),
-            git2::SubmoduleIgnore::Dirty,
-        )?;
-        if status.is_index_modified() || status.is_index_added() {
-            if modified_submodule.is_none() {
-                modified_submodule = Some(submodule);
+/// Finds staged PKGBUILDs and submodules, in the order of their paths.
+fn find_changes(
+    repo: &git2::Repository,
+    staged: &[StagedDelta],
+) -> Result<Vec<PKGBUILDChange>, git2::Error> {
+    let mut changes = Vec::new();
+    for delta in staged {
+        if delta.status != git2::Delta::Added && delta.status != git2::Delta::Modified {
+            continue;
+        }
+        if delta.new_mode == git2::FileMode::Commit {
+            changes.push(submodule_change(repo, delta)?);
+        } else if delta.path.file_name() == Some(std::ffi::OsStr::new("PKGBUILD")) {
+            let old_pkgbuild = if delta.status == git2::Delta::Modified {
+                Some(repo.find_blob(delta.old_id)?.content().to_vec())
             } else {
-                panic!("Multiple submodules are modified");
-            }
+                None
+            };
+            let dir = delta.path.parent().expect("Invalid PKGBUILD path");
+            changes.push(PKGBUILDChange {
+                new_pkgbuild: repo.find_blob(delta.new_id)?.content().to_vec(),
+                old_pkgbuild,
+                pkgname: path_to_pkgname(dir),
+                path: dir.to_owned(),
+                submodule: false,
+            });
         }
     }
+    Ok(changes)
+}
 
-    let modified_submodule = match modified_submodule {
-        Some(submodule) => submodule,
-        None => return Ok(None),
-    };
-
+fn submodule_change(
+    repo: &git2::Repository,
+    delta: &StagedDelta,
+) -> Result<PKGBUILDChange, git2::Error> {
     let path = repo
         .path()
         .parent()
         .unwrap_or_else(|| repo.path())
-        .join(modified_submodule.path());
+        .join(&delta.path);
     let sub_repo = git2::Repository::open(path)?;
-    let index_id = modified_submodule
-        .index_id()
-        .expect("Unable to get index id of the submodule");
-    let new_pkgbuild_content = get_pkgbuild_content(&sub_repo, index_id, "PKGBUILD")?;
-    let old_pkgbuild_content = if let Some(head_id) = modified_submodule.head_id() {
-        Some(get_pkgbuild_content(&sub_repo, head_id, "PKGBUILD")?)
+    let new_pkgbuild_content = get_pkgbuild_content(&sub_repo, delta.new_id, "PKGBUILD")?;
+    let old_pkgbuild_content = if delta.status == git2::Delta::Modified {
+        Some(get_pkgbuild_content(&sub_repo, delta.old_id, "PKGBUILD")?)
     } else {
         None
     };
 
-    let pkgname = path_to_pkgname(modified_submodule.path());
-    Ok(Some(PKGBUILDChange {
+    Ok(PKGBUILDChange {
         new_pkgbuild: new_pkgbuild_content,
         old_pkgbuild: old_pkgbuild_content,
-        pkgname,
-    }))
+        pkgname: path_to_pkgname(&delta.path),
+        path: delta.path.clone(),
+        submodule: true,
+    })
+}
+
+/// Returns the index of the change which a staged file belongs to.
+/// `.gitmodules` is staged along with added submodules, so it goes with the first submodule.
+fn owner(path: &std::path::Path, changes: &[PKGBUILDChange]) -> Option<usize> {
+    if path == std::path::Path::new(".gitmodules") {
+        changes.iter().position(|c| c.submodule)
+    } else {
+        changes.iter().position(|c| path.starts_with(&c.path))
+    }
+}
+
+/// Unstages everything, then stages and commits the files of each package in turn.
+/// Files which are staged but don't belong to any package are refused, since they would be left
+/// out of every commit.
+fn commit_separately(
+    repo: &git2::Repository,
+    staged: &[StagedDelta],
+    changes: &[PKGBUILDChange],
+    messages: &[String],
+) -> Result<(), anyhow::Error> {
+    let unowned: Vec<_> = staged
+        .iter()
+        .filter(|delta| owner(&delta.path, changes).is_none())
+        .map(|delta| delta.path.display().to_string())
+        .collect();
+    if !unowned.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Staged files not belonging to any package: {}",
+            unowned.join(", ")
+        ));
+    }
+
+    // Keep the staged entries to restage them later
+    let index = repo.index()?;
+    let mut groups: Vec<Vec<(&StagedDelta, Option<git2::IndexEntry>)>> =
+        changes.iter().map(|_| Vec::new()).collect();
+    for delta in staged {
+        let i = owner(&delta.path, changes).unwrap();
+        groups[i].push((delta, index.get_path(&delta.path, 0)));
+    }
+    let head = repo.head()?.peel_to_commit()?;
+    repo.reset_default(
+        Some(head.as_object()),
+        staged.iter().map(|delta| delta.path.as_path()),
+    )?;
+
+    for (i, message) in messages.iter().enumerate() {
+        stage(repo, &groups[i])?;
+        let status = std::process::Command::new("git")
+            .arg("commit")
+            .arg("-m")
+            .arg(message)
+            .status()?;
+        if !status.success() {
+            // Leave the rest staged as before
+            for group in &groups[i + 1..] {
+                stage(repo, group)?;
+            }
+            return Err(anyhow::anyhow!("git commit failed for {}", message));
+        }
+    }
+    Ok(())
+}
+
+fn stage(
+    repo: &git2::Repository,
+    entries: &[(&StagedDelta, Option<git2::IndexEntry>)],
+) -> Result<(), git2::Error> {
+    let mut index = repo.index()?;
+    index.read(true)?;
+    for (delta, entry) in entries {
+        match entry {
+            Some(entry) => index.add(entry)?,
+            None => index.remove_path(&delta.path)?,
+        }
+    }
+    index.write()
 }
 
 fn get_pkgbuild_content<P>(
@@ -158,18 +266,9 @@ fn path_to_pkgname(path: &std::path::Path) -> String {
         .expect("Invalid UTF-8 sequence is found at pkgname")
 }
 
-fn evaluate_pkgbuild(content: &[u8]) -> Result<String, std::io::Error> {
-    use std::io::Write;
-
-    let mut child = std::process::Command::new("bash")
-        .stdin(std::process::Stdio::piped())
-        .stdout(std::process::Stdio::piped())
-        .spawn()?;
-    {
-        let mut stdin = child.stdin.take().unwrap();
-        stdin.write_all(content)?;
-        stdin.write_all(b"\necho -n $pkgver-$pkgrel")?;
-    }
-    let output = child.wait_with_output()?;
-    Ok(String::from_utf8(output.stdout).expect("Invalid UTF-8 sequence in PKGBUILD output"))
+/// Returns `[epoch:]pkgver-pkgrel` of PKGBUILD without running it, since PKGBUILDs of AUR
+/// submodules are written by third parties.
+fn evaluate_pkgbuild(content: &[u8]) -> Result<String, anyhow::Error> {
+    let pkgbuild: arch_wanko_cc::pkgbuild::Pkgbuild = std::str::from_utf8(content)?.parse()?;
+    pkgbuild.evaluate().full_version()
 }
@@ -1,181 +1,343 @@
+#[derive(structopt::StructOpt)]
+struct Opt {
+    /// Read and write local mirrors of the buckets instead of S3, each in a directory named after
+    /// its bucket
+    #[structopt(long, parse(from_os_str))]
+    local_dir: Option<std::path::PathBuf>,
+    /// Print differences against the stored pages instead of uploading them
+    #[structopt(long)]
+    dry_run: bool,
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     use futures::StreamExt as _;
+    use structopt::StructOpt as _;
 
-    let bucket = std::env::var("BUCKET").expect("BUCKET variable is missing");
-    let index_input = IndexInput {
-        repositories: vec![
-            "aur-eagletmt".to_owned(),
-            "vim-latest".to_owned(),
-            "ruby-trunk".to_owned(),
-        ],
-    };
+    let opt = Opt::from_args();
+    let repositories = arch_wanko_cc::guzuta::Repository::discover(".")?;
+
+    let mut buckets = std::collections::BTreeMap::new();
+    for repo in &repositories {
+        let s3 = repo.s3()?;
+        let url = repo.site_url()?;
+        let key = (s3.bucket.clone(), s3.region.clone());
+        if !buckets.contains_key(&key) {
+            buckets.insert(key.clone(), (Site::new(s3, url, &opt)?, Vec::new()));
+        }
+        let (site, names) = buckets.get_mut(&key).unwrap();
+        if site.url != url {
+            return Err(anyhow::anyhow!(
+                "{} is served at {}, but {} is written for the same bucket in {}",
+                s3.bucket,
+                site.url,
+                url,
+                repo.name()
+            )
+            .into());
+        }
+        names.push(repo.name().to_owned());
+    }
 
     let mut package_inputs = futures::stream::FuturesUnordered::new();
-    for repo in &index_input.repositories {
-        package_inputs.push(fetch_repository_input(bucket.clone(), repo.clone()));
+    for repo in &repositories {
+        let s3 = repo.s3()?;
+        package_inputs.push(fetch_repository_input(
+            buckets[&(s3.bucket.clone(), s3.region.clone())].0.clone(),
+            repo.name().to_owned(),
+            repo.architectures().map(ToOwned::to_owned).collect(),
+        ));
     }
 
-    let mut handlebars = handlebars::Handlebars::new();
-    handlebars.set_strict_mode(true);
-    handlebars.register_template_file("index", "templates/index.html")?;
-    handlebars.register_template_file("repository", "templates/repository.html")?;
-
-    let mut html_uploads = futures::stream::FuturesUnordered::new();
-    html_uploads.push(upload_html(
-        bucket.clone(),
-        "index.html".to_owned(),
-        handlebars.render("index", &index_input)?,
-    ));
+    let handlebars = arch_wanko_cc::pages::templates("templates")?;
+
+    let mut uploads = futures::stream::FuturesUnordered::new();
+    for (site, repositories) in buckets.values() {
+        uploads.push(upload(
+            site.clone(),
+            "index.json".to_owned(),
+            JSON,
+            serde_json::to_string_pretty(&arch_wanko_cc::pages::IndexJson::new(repositories))?,
+        ));
+        uploads.push(upload(
+            site.clone(),
+            "index.html".to_owned(),
+            HTML,
+            handlebars.render("index", &IndexInput { repositories })?,
+        ));
+    }
+    let mut repository_inputs = Vec::new();
     while let Some(package_input) = package_inputs.next().await {
-        let package_input = package_input?;
-        html_uploads.push(upload_html(
-            bucket.clone(),
+        repository_inputs.push(package_input?);
+    }
+    repository_inputs.sort_by(|(_, a), (_, b)| a.name.cmp(&b.name));
+    let package_links =
+        arch_wanko_cc::pages::PackageLinks::new(repository_inputs.iter().map(|(_, r)| r));
+    let mut feed_entries = std::collections::HashMap::new();
+    for (site, package_input) in &repository_inputs {
+        let mut feed_state =
+            arch_wanko_cc::pages::FeedState::fetch(&*site.storage, &package_input.name).await?;
+        feed_state.update(
+            &package_input.name,
+            package_input.architectures.iter().flat_map(|arch| {
+                arch.packages
+                    .iter()
+                    .map(move |package| (arch.name.as_str(), &package.desc))
+            }),
+        );
+        uploads.push(upload(
+            site.clone(),
+            format!("{}/feed.atom", package_input.name),
+            ATOM,
+            handlebars.render(
+                "feed",
+                &FeedInput::new(
+                    site,
+                    format!("{} - arch.wanko.cc", package_input.name),
+                    &format!("/{}/", package_input.name),
+                    &feed_state.entries,
+                ),
+            )?,
+        ));
+        uploads.push(upload(
+            site.clone(),
+            format!("{}/feed-state.json", package_input.name),
+            JSON,
+            serde_json::to_string_pretty(&feed_state)?,
+        ));
+        feed_entries
+            .entry(site.url.clone())
+            .or_insert_with(Vec::new)
+            .extend(feed_state.entries);
+
+        uploads.push(upload(
+            site.clone(),
             format!("{}/index.html", package_input.name),
-            handlebars.render("repository", &package_input)?,
+            HTML,
+            handlebars.render("repository", package_input)?,
+        ));
+        uploads.push(upload(
+            site.clone(),
+            format!("{}/packages.json", package_input.name),
+            JSON,
+            serde_json::to_string_pretty(&arch_wanko_cc::pages::PackagesJson::new(package_input))?,
         ));
+        uploads.push(upload(
+            site.clone(),
+            format!("{}/files/index.html", package_input.name),
+            HTML,
+            handlebars.render("files", &package_input.files_page())?,
+        ));
+        for page_input in package_input.package_pages(&package_links) {
+            uploads.push(upload(
+                site.clone(),
+                format!(
+                    "{}/packages/{}/index.html",
+                    package_input.name, page_input.name
+                ),
+                HTML,
+                handlebars.render("package", &page_input)?,
+            ));
+        }
+    }
+    for (site, _) in buckets.values() {
+        let mut entries = feed_entries.remove(&site.url).unwrap_or_default();
+        arch_wanko_cc::pages::sort_feed_entries(&mut entries);
+        uploads.push(upload(
+            site.clone(),
+            "feed.atom".to_owned(),
+            ATOM,
+            handlebars.render(
+                "feed",
+                &FeedInput::new(site, "arch.wanko.cc".to_owned(), "/", &entries),
+            )?,
+        ));
+    }
+    let mut uploaded = 0;
+    let mut skipped = 0;
+    let mut failed = 0;
+    let mut diffs = std::collections::BTreeMap::new();
+    while let Some(r) = uploads.next().await {
+        match r {
+            Ok((_, arch_wanko_cc::pages::UploadStatus::Uploaded)) => uploaded += 1,
+            Ok((_, arch_wanko_cc::pages::UploadStatus::Skipped)) => skipped += 1,
+            Ok((key, arch_wanko_cc::pages::UploadStatus::Changed(diff))) => {
+                // Every bucket has its own index.html and so on
+                diffs.insert(key, diff);
+            }
+            Err(e) => {
+                eprintln!("{:#}", e);
+                failed += 1;
+            }
+        }
+    }
+    if opt.dry_run {
+        for ((bucket, key), diff) in &diffs {
+            println!("diff s3://{}/{}", bucket, key);
+            print!("{}", diff);
+        }
+        println!(
+            "changed: {}, unchanged: {}, failed: {}",
+            diffs.len(),
+            skipped,
+            failed
+        );
+    } else {
+        println!(
+            "uploaded: {}, skipped: {}, failed: {}",
+            uploaded, skipped, failed
+        );
     }
-    while let Some(r) = html_uploads.next().await {
-        r?;
+
+    if failed == 0 {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!("Failed to upload {} pages", failed).into())
     }
+}
 
-    Ok(())
+/// A bucket serving pages under `url`.
+#[derive(Clone)]
+struct Site {
+    url: String,
+    bucket: String,
+    storage: std::sync::Arc<dyn arch_wanko_cc::storage::Storage>,
+    dry_run: bool,
+}
+
+impl Site {
+    fn new(
+        s3: &arch_wanko_cc::guzuta::S3Config,
+        url: &str,
+        opt: &Opt,
+    ) -> Result<Self, anyhow::Error> {
+        let storage: std::sync::Arc<dyn arch_wanko_cc::storage::Storage> = match opt.local_dir {
+            Some(ref dir) => std::sync::Arc::new(arch_wanko_cc::storage::LocalStorage::new(
+                dir.join(&s3.bucket),
+            )),
+            None => std::sync::Arc::new(arch_wanko_cc::storage::S3Storage::new(s3)?),
+        };
+        Ok(Self {
+            url: url.to_owned(),
+            bucket: s3.bucket.clone(),
+            storage,
+            dry_run: opt.dry_run,
+        })
+    }
 }
 
 #[derive(serde::Serialize)]
-struct IndexInput {
-    repositories: Vec<String>,
+struct IndexInput<'a> {
+    repositories: &'a [String],
 }
 
 #[derive(serde::Serialize)]
-struct RepositoryInput {
-    name: String,
-    packages: Vec<PackageInput>,
+struct FeedInput<'a> {
+    title: String,
+    id: String,
+    alternate_url: String,
+    updated: String,
+    entries: Vec<FeedEntryInput<'a>>,
 }
 #[derive(serde::Serialize)]
-struct PackageInput {
-    name: String,
-    version: String,
-    filename: String,
-    builddate_timestamp: i64,
-    builddate_str: String,
+struct FeedEntryInput<'a> {
+    #[serde(flatten)]
+    entry: &'a arch_wanko_cc::pages::FeedEntry,
+    id: String,
+    url: String,
+    updated: String,
 }
 
-async fn fetch_repository_input(
-    bucket: String,
-    name: String,
-) -> Result<RepositoryInput, anyhow::Error> {
-    use futures::TryStreamExt as _;
-    use rusoto_s3::S3 as _;
-    use std::io::Read as _;
-
-    let s3_client = rusoto_s3::S3Client::new(Default::default());
-    let resp = s3_client
-        .get_object(rusoto_s3::GetObjectRequest {
-            bucket: bucket.to_owned(),
-            key: format!("{}/os/x86_64/{}.db", name, name),
-            ..Default::default()
-        })
-        .await?;
-    let body = resp
-        .body
-        .unwrap()
-        .map_ok(|b| bytes::BytesMut::from(&b[..]))
-        .try_concat()
-        .await?;
-    let gz_reader = flate2::read::GzDecoder::new(body.as_ref());
-    let mut tar_reader = tar::Archive::new(gz_reader);
-    let mut packages = Vec::new();
-    for entry in tar_reader.entries()? {
-        let mut entry = entry?;
-        let path = entry.path()?;
-        match path.file_name() {
-            Some(filename) if filename == "desc" => {
-                let mut desc_body = String::new();
-                entry.read_to_string(&mut desc_body)?;
-                packages.push(parse_desc(&desc_body)?);
-            }
-            _ => {}
+impl<'a> FeedInput<'a> {
+    fn new(
+        site: &Site,
+        title: String,
+        path: &str,
+        entries: &'a [arch_wanko_cc::pages::FeedEntry],
+    ) -> Self {
+        let site_url = &site.url;
+        Self {
+            title,
+            id: format!("{}{}feed.atom", site_url, path),
+            alternate_url: format!("{}{}", site_url, path),
+            // Empty feeds are dated at the epoch rather than now, so that they stay unchanged
+            // and aren't uploaded on every run
+            updated: arch_wanko_cc::pages::format_timestamp(
+                entries
+                    .iter()
+                    .map(|entry| entry.builddate)
+                    .max()
+                    .unwrap_or(0),
+            ),
+            entries: entries
+                .iter()
+                .map(|entry| {
+                    let url = format!("{}/{}/packages/{}/", site_url, entry.repository, entry.name);
+                    FeedEntryInput {
+                        entry,
+                        id: format!(
+                            "{}#{}-{}-{}",
+                            url, entry.architecture, entry.version, entry.builddate
+                        ),
+                        url,
+                        updated: arch_wanko_cc::pages::format_timestamp(entry.builddate),
+                    }
+                })
+                .collect(),
         }
     }
-    Ok(RepositoryInput {
-        name: name.to_owned(),
-        packages,
-    })
 }
 
-fn parse_desc(body: &str) -> Result<PackageInput, anyhow::Error> {
-    let mut name = None;
-    let mut version = None;
-    let mut filename = None;
-    let mut builddate = None;
-
-    let mut key = "";
-    for line in body.lines() {
-        let line = line.trim();
-        if line.starts_with('%') && line.ends_with('%') {
-            key = &line[1..line.len() - 1];
-        } else if line.is_empty() {
-            key = "";
-        } else {
-            match key {
-                "NAME" => {
-                    name = Some(line.to_owned());
-                }
-                "VERSION" => {
-                    version = Some(line.to_owned());
-                }
-                "FILENAME" => {
-                    filename = Some(line.to_owned());
-                }
-                "BUILDDATE" => {
-                    builddate = Some(line.parse()?);
-                }
-                _ => {}
-            }
+async fn fetch_repository_input(
+    site: Site,
+    name: String,
+    architectures: Vec<String>,
+) -> Result<(Site, arch_wanko_cc::pages::RepositoryInput), anyhow::Error> {
+    let mut architecture_inputs = Vec::new();
+    for arch in architectures {
+        let db = fetch_object(&site, format!("{}/os/{}/{}.db", name, arch, name));
+        let files_key = format!("{}/os/{}/{}.files", name, arch, name);
+        let files_db = site.storage.get(&files_key);
+        let (db, files_db) = futures::try_join!(db, files_db)?;
+        if files_db.is_none() {
+            // Packages are still listed, just without their files
+            eprintln!("{}/{} is missing, listing no files", site.url, files_key);
         }
+        architecture_inputs.push(arch_wanko_cc::pages::ArchitectureInput::read(
+            arch,
+            &db,
+            files_db.as_deref(),
+        )?);
     }
+    let input = arch_wanko_cc::pages::RepositoryInput {
+        name,
+        url: site.url.clone(),
+        architectures: architecture_inputs,
+    };
+    Ok((site, input))
+}
 
-    if name.is_none() {
-        return Err(anyhow::anyhow!("Failed to find NAME from desc file"));
-    }
-    if version.is_none() {
-        return Err(anyhow::anyhow!("Failed to find VERSION from desc file"));
-    }
-    if filename.is_none() {
-        return Err(anyhow::anyhow!("Failed to find FILENAME from desc file"));
-    }
-    if builddate.is_none() {
-        return Err(anyhow::anyhow!("Failed to find BUILDDATE from desc file"));
-    }
-    Ok(PackageInput {
-        name: name.unwrap(),
-        version: version.unwrap(),
-        filename: filename.unwrap(),
-        builddate_timestamp: builddate.unwrap(),
-        builddate_str: chrono::DateTime::<chrono::Utc>::from_utc(
-            chrono::NaiveDateTime::from_timestamp(builddate.unwrap(), 0),
-            chrono::Utc,
-        )
-        .to_rfc3339(),
-    })
+async fn fetch_object(site: &Site, key: String) -> Result<Vec<u8>, anyhow::Error> {
+    site.storage
+        .get(&key)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("{}/{} is missing", site.url, key))
 }
 
-async fn upload_html(bucket: String, key: String, body: String) -> Result<(), anyhow::Error> {
-    use md5::Digest as _;
-    use rusoto_s3::S3 as _;
-
-    let s3_client = rusoto_s3::S3Client::new(Default::default());
-    let content_md5 = Some(base64::encode(md5::Md5::digest(body.as_bytes())));
-    s3_client
-        .put_object(rusoto_s3::PutObjectRequest {
-            bucket,
-            key,
-            content_type: Some("text/html; charset=utf-8".to_owned()),
-            content_md5,
-            body: Some(body.into_bytes().into()),
-            ..Default::default()
-        })
-        .await?;
-    Ok(())
+const HTML: &str = "text/html; charset=utf-8";
+const JSON: &str = "application/json; charset=utf-8";
+const ATOM: &str = "application/atom+xml; charset=utf-8";
+
+/// Uploads `body` to the site with `arch_wanko_cc::pages::upload`, returning the bucket and `key`
+/// with the status.
+async fn upload(
+    site: Site,
+    key: String,
+    content_type: &'static str,
+    body: String,
+) -> Result<((String, String), arch_wanko_cc::pages::UploadStatus), anyhow::Error> {
+    let status =
+        arch_wanko_cc::pages::upload(&*site.storage, site.dry_run, &key, content_type, body)
+            .await?;
+    Ok(((site.bucket, key), status))
 }
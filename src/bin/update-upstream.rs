@@ -0,0 +1,104 @@
+#[derive(structopt::StructOpt)]
+struct Opt {
+    /// Package directories having upstream.yml. Every package is updated when omitted
+    #[structopt(parse(from_os_str))]
+    packages: Vec<std::path::PathBuf>,
+    #[structopt(flatten)]
+    client: arch_wanko_cc::github::ClientOpt,
+    /// Delete files in srcdest which no PKGBUILD refers to, instead of updating packages
+    #[structopt(long)]
+    prune: bool,
+    /// Update the given package to this commit instead of the latest one
+    #[structopt(long)]
+    commit: Option<String>,
+}
+
+/// Exit status when no package has anything new upstream.
+const EXIT_UP_TO_DATE: i32 = 2;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    use structopt::StructOpt as _;
+
+    let opt = Opt::from_args();
+    let packages = if opt.packages.is_empty() {
+        arch_wanko_cc::upstream::Package::discover(".")?
+    } else {
+        opt.packages
+            .iter()
+            .map(arch_wanko_cc::upstream::Package::load)
+            .collect::<Result<_, _>>()?
+    };
+
+    if opt.commit.is_some() && packages.len() != 1 {
+        return Err("--commit needs exactly one package directory".into());
+    }
+    if opt.prune {
+        prune(&packages)?;
+        return Ok(());
+    }
+
+    let client = opt.client.client()?;
+    let result = update_packages(&client, packages, opt.commit.as_deref()).await;
+    client.save_cache()?;
+    if result? {
+        Ok(())
+    } else {
+        std::process::exit(EXIT_UP_TO_DATE);
+    }
+}
+
+/// Returns true when any package is updated.
+async fn update_packages(
+    client: &arch_wanko_cc::github::Client,
+    packages: Vec<arch_wanko_cc::upstream::Package>,
+    commit: Option<&str>,
+) -> Result<bool, anyhow::Error> {
+    let mut updated = false;
+    for package in packages {
+        let status = match commit {
+            Some(commit) => package.update_to_commit(client, commit).await?,
+            None => package.update(client).await?,
+        };
+        match status {
+            arch_wanko_cc::upstream::UpdateStatus::Updated => {
+                println!("{} is updated", package.name);
+                updated = true;
+            }
+            arch_wanko_cc::upstream::UpdateStatus::UpToDate => {
+                println!("{} is up to date", package.name);
+            }
+        }
+    }
+    Ok(updated)
+}
+
+/// Prunes srcdest of each repository having the packages.
+fn prune(packages: &[arch_wanko_cc::upstream::Package]) -> Result<(), anyhow::Error> {
+    let mut pruned = std::collections::HashSet::new();
+    for package in packages {
+        let repository = &package.repository;
+        if !pruned.insert(repository.dir.clone()) {
+            continue;
+        }
+        let report = arch_wanko_cc::upstream::prune(repository)?;
+        let mut removed_size = 0;
+        for (path, size) in &report.removed {
+            println!(
+                "Removed {} ({})",
+                path.display(),
+                arch_wanko_cc::format_size(*size)
+            );
+            removed_size += size;
+        }
+        println!(
+            "{}: removed {} files ({}), kept {} files ({})",
+            repository.name(),
+            report.removed.len(),
+            arch_wanko_cc::format_size(removed_size),
+            report.kept,
+            arch_wanko_cc::format_size(report.kept_size)
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,125 @@
+/// Package metadata stored as `desc` entries in pacman repository databases.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Desc {
+    pub filename: String,
+    pub name: String,
+    pub base: Option<String>,
+    pub version: String,
+    pub desc: Option<String>,
+    pub groups: Vec<String>,
+    pub csize: Option<u64>,
+    pub isize: Option<u64>,
+    pub md5sum: Option<String>,
+    pub sha256sum: Option<String>,
+    pub pgpsig: Option<String>,
+    pub url: Option<String>,
+    pub license: Vec<String>,
+    pub arch: Option<String>,
+    pub builddate: i64,
+    pub packager: Option<String>,
+    pub replaces: Vec<String>,
+    pub conflicts: Vec<String>,
+    pub provides: Vec<String>,
+    pub depends: Vec<String>,
+    pub optdepends: Vec<String>,
+    pub makedepends: Vec<String>,
+    pub checkdepends: Vec<String>,
+    /// Fields this parser doesn't know about, keyed by their name without `%`.
+    pub extra: std::collections::BTreeMap<String, Vec<String>>,
+}
+
+/// Splits `%KEY%` sections of a repository database entry into their values.
+pub fn parse_sections(body: &str) -> std::collections::BTreeMap<String, Vec<String>> {
+    let mut sections = std::collections::BTreeMap::new();
+    let mut values: Option<&mut Vec<String>> = None;
+    for line in body.lines() {
+        let line = line.trim();
+        if line.len() >= 2 && line.starts_with('%') && line.ends_with('%') {
+            values = Some(
+                sections
+                    .entry(line[1..line.len() - 1].to_owned())
+                    .or_insert_with(Vec::new),
+            );
+        } else if line.is_empty() {
+            values = None;
+        } else if let Some(ref mut values) = values {
+            values.push(line.to_owned());
+        }
+    }
+    sections
+}
+
+/// Returns the paths listed in a `files` entry of a `<repo>.files` database.
+pub fn parse_files(body: &str) -> Vec<String> {
+    parse_sections(body).remove("FILES").unwrap_or_default()
+}
+
+impl std::str::FromStr for Desc {
+    type Err = anyhow::Error;
+
+    fn from_str(body: &str) -> Result<Self, Self::Err> {
+        let mut fields = parse_sections(body);
+        let builddate = take_one(&mut fields, "BUILDDATE")?
+            .ok_or_else(|| anyhow::anyhow!("Failed to find BUILDDATE from desc file"))?;
+        Ok(Self {
+            filename: take_one(&mut fields, "FILENAME")?
+                .ok_or_else(|| anyhow::anyhow!("Failed to find FILENAME from desc file"))?,
+            name: take_one(&mut fields, "NAME")?
+                .ok_or_else(|| anyhow::anyhow!("Failed to find NAME from desc file"))?,
+            base: take_one(&mut fields, "BASE")?,
+            version: take_one(&mut fields, "VERSION")?
+                .ok_or_else(|| anyhow::anyhow!("Failed to find VERSION from desc file"))?,
+            desc: take_one(&mut fields, "DESC")?,
+            groups: take_all(&mut fields, "GROUPS"),
+            csize: take_one(&mut fields, "CSIZE")?
+                .map(|s| s.parse())
+                .transpose()?,
+            isize: take_one(&mut fields, "ISIZE")?
+                .map(|s| s.parse())
+                .transpose()?,
+            md5sum: take_one(&mut fields, "MD5SUM")?,
+            sha256sum: take_one(&mut fields, "SHA256SUM")?,
+            pgpsig: take_one(&mut fields, "PGPSIG")?,
+            url: take_one(&mut fields, "URL")?,
+            license: take_all(&mut fields, "LICENSE"),
+            arch: take_one(&mut fields, "ARCH")?,
+            builddate: builddate.parse()?,
+            packager: take_one(&mut fields, "PACKAGER")?,
+            replaces: take_all(&mut fields, "REPLACES"),
+            conflicts: take_all(&mut fields, "CONFLICTS"),
+            provides: take_all(&mut fields, "PROVIDES"),
+            depends: take_all(&mut fields, "DEPENDS"),
+            optdepends: take_all(&mut fields, "OPTDEPENDS"),
+            makedepends: take_all(&mut fields, "MAKEDEPENDS"),
+            checkdepends: take_all(&mut fields, "CHECKDEPENDS"),
+            extra: fields,
+        })
+    }
+}
+
+fn take_one(
+    fields: &mut std::collections::BTreeMap<String, Vec<String>>,
+    key: &str,
+) -> Result<Option<String>, anyhow::Error> {
+    match fields.remove(key) {
+        Some(mut values) => {
+            if values.len() <= 1 {
+                Ok(values.pop())
+            } else {
+                Err(anyhow::anyhow!(
+                    "Expected a single value for {} in desc file, but got {}",
+                    key,
+                    values.len()
+                ))
+            }
+        }
+        None => Ok(None),
+    }
+}
+
+fn take_all(
+    fields: &mut std::collections::BTreeMap<String, Vec<String>>,
+    key: &str,
+) -> Vec<String> {
+    fields.remove(key).unwrap_or_default()
+}
@@ -0,0 +1,302 @@
+pub const API_URL: &str = "https://api.github.com";
+pub const WEB_URL: &str = "https://github.com";
+
+const ACCEPT: &str = "application/vnd.github.v3+json";
+const USER_AGENT: &str = "arch.wanko.cc/0.0.0 https://github.com/eagletmt/arch.wanko.cc";
+/// How many times a rate-limited request is retried.
+const MAX_RETRIES: usize = 3;
+/// Upper bound of waiting for the rate limit to reset, in seconds.
+const MAX_WAIT: u64 = 3600;
+
+/// Command line options to set up `Client`, flattened into the options of each binary.
+#[derive(Debug, structopt::StructOpt)]
+pub struct ClientOpt {
+    /// File to keep ETags and responses of GitHub API between runs
+    #[structopt(long, parse(from_os_str), default_value = ".github-cache.json")]
+    cache_file: std::path::PathBuf,
+    /// Base URL of GitHub REST API
+    #[structopt(long)]
+    api_url: Option<String>,
+    /// Base URL of GitHub to download source tarballs from
+    #[structopt(long)]
+    web_url: Option<String>,
+}
+
+impl ClientOpt {
+    /// Creates a client with the base URLs and the cache file of the options.
+    pub fn client(&self) -> Result<Client, anyhow::Error> {
+        Client::new()?
+            .with_base_urls(
+                self.api_url.as_deref().unwrap_or(API_URL),
+                self.web_url.as_deref().unwrap_or(WEB_URL),
+            )
+            .with_cache(&self.cache_file)
+    }
+}
+
+/// GitHub client shared by the updaters.
+/// It authenticates with `GITHUB_TOKEN` when set, waits for the rate limit to reset on 403/429 and
+/// sends conditional requests with the ETags in the cache.
+pub struct Client {
+    client: reqwest::Client,
+    api_url: String,
+    web_url: String,
+    token: Option<String>,
+    cache_path: Option<std::path::PathBuf>,
+    cache: std::sync::Mutex<std::collections::BTreeMap<String, CacheEntry>>,
+    /// URLs requested in this run. Only their entries are saved, so that the cache doesn't keep
+    /// responses nobody asks for anymore.
+    used: std::sync::Mutex<std::collections::BTreeSet<String>>,
+}
+
+/// API response saved with its ETag. 304 Not Modified responses are answered from this.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct CacheEntry {
+    etag: String,
+    next: Option<String>,
+    body: String,
+}
+
+impl Client {
+    pub fn new() -> Result<Self, anyhow::Error> {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            reqwest::header::USER_AGENT,
+            reqwest::header::HeaderValue::from_static(USER_AGENT),
+        );
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .build()?;
+        let token = std::env::var("GITHUB_TOKEN")
+            .ok()
+            .filter(|token| !token.is_empty());
+        Ok(Self {
+            client,
+            api_url: API_URL.to_owned(),
+            web_url: WEB_URL.to_owned(),
+            token,
+            cache_path: None,
+            cache: std::sync::Mutex::new(std::collections::BTreeMap::new()),
+            used: std::sync::Mutex::new(std::collections::BTreeSet::new()),
+        })
+    }
+
+    /// Replaces the base URLs of the REST API and source tarballs, such as a local server in tests.
+    pub fn with_base_urls<A, W>(mut self, api_url: A, web_url: W) -> Self
+    where
+        A: Into<String>,
+        W: Into<String>,
+    {
+        self.api_url = api_url.into().trim_end_matches('/').to_owned();
+        self.web_url = web_url.into().trim_end_matches('/').to_owned();
+        self
+    }
+
+    /// Loads ETags and responses from `path`. The file is created by `save_cache`.
+    pub fn with_cache<P>(mut self, path: P) -> Result<Self, anyhow::Error>
+    where
+        P: Into<std::path::PathBuf>,
+    {
+        use anyhow::Context as _;
+
+        let path = path.into();
+        match std::fs::File::open(&path) {
+            Ok(file) => {
+                let cache = serde_json::from_reader(std::io::BufReader::new(file))
+                    .with_context(|| format!("Failed to parse {}", path.display()))?;
+                self.cache = std::sync::Mutex::new(cache);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(
+                    anyhow::Error::new(e).context(format!("Failed to open {}", path.display()))
+                )
+            }
+        }
+        self.cache_path = Some(path);
+        Ok(self)
+    }
+
+    /// Writes the entries of the URLs requested in this run back to the file given to
+    /// `with_cache`, replacing it atomically.
+    pub fn save_cache(&self) -> Result<(), anyhow::Error> {
+        if let Some(ref path) = self.cache_path {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let cache = self.cache.lock().unwrap();
+            let used = self.used.lock().unwrap();
+            let entries: std::collections::BTreeMap<_, _> = cache
+                .iter()
+                .filter(|(url, _)| used.contains(*url))
+                .collect();
+            crate::write_atomically(path, &serde_json::to_vec(&entries)?)?;
+        }
+        Ok(())
+    }
+
+    /// Sends a GET request to the REST API. `path` starts with `/`.
+    pub async fn get<T>(&self, path: &str) -> Result<T, anyhow::Error>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let entry = self.fetch(&format!("{}{}", self.api_url, path)).await?;
+        Ok(serde_json::from_str(&entry.body)?)
+    }
+
+    /// Sends a GET request to the REST API with query parameters.
+    pub async fn get_with_query<T>(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<T, anyhow::Error>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let url = reqwest::Url::parse_with_params(&format!("{}{}", self.api_url, path), query)?;
+        let entry = self.fetch(url.as_str()).await?;
+        Ok(serde_json::from_str(&entry.body)?)
+    }
+
+    /// Sends GET requests to the REST API following `Link: <...>; rel="next"` headers and
+    /// concatenates every page.
+    pub async fn get_all<T>(&self, path: &str) -> Result<Vec<T>, anyhow::Error>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let separator = if path.contains('?') { '&' } else { '?' };
+        let mut next = Some(format!("{}{}{}per_page=100", self.api_url, path, separator));
+        let mut items = Vec::new();
+        while let Some(url) = next {
+            let entry = self.fetch(&url).await?;
+            let page: Vec<T> = serde_json::from_str(&entry.body)?;
+            items.extend(page);
+            next = entry.next;
+        }
+        Ok(items)
+    }
+
+    async fn fetch(&self, url: &str) -> Result<CacheEntry, anyhow::Error> {
+        self.used.lock().unwrap().insert(url.to_owned());
+        let cached = self.cache.lock().unwrap().get(url).cloned();
+        let mut retries = 0;
+        loop {
+            let mut req = self.client.get(url).header(
+                reqwest::header::ACCEPT,
+                reqwest::header::HeaderValue::from_static(ACCEPT),
+            );
+            if let Some(ref token) = self.token {
+                req = req.header(reqwest::header::AUTHORIZATION, format!("token {}", token));
+            }
+            if let Some(ref entry) = cached {
+                req = req.header(reqwest::header::IF_NONE_MATCH, &entry.etag);
+            }
+            let resp = req.send().await?;
+            let status = resp.status();
+            if status == reqwest::StatusCode::NOT_MODIFIED {
+                if let Some(entry) = cached {
+                    return Ok(entry);
+                }
+            }
+            if retries < MAX_RETRIES {
+                if let Some(wait) = rate_limit_wait(&resp) {
+                    eprintln!(
+                        "Rate limited by {} ({}), retrying in {} seconds",
+                        url,
+                        status,
+                        wait.as_secs()
+                    );
+                    tokio::time::delay_for(wait).await;
+                    retries += 1;
+                    continue;
+                }
+            }
+            let resp = resp.error_for_status()?;
+            let etag = resp
+                .headers()
+                .get(reqwest::header::ETAG)
+                .and_then(|etag| etag.to_str().ok())
+                .map(ToOwned::to_owned);
+            let next = resp
+                .headers()
+                .get(reqwest::header::LINK)
+                .and_then(|link| link.to_str().ok())
+                .and_then(next_link);
+            let body = resp.text().await?;
+            let entry = CacheEntry {
+                etag: etag.unwrap_or_default(),
+                next,
+                body,
+            };
+            let mut cache = self.cache.lock().unwrap();
+            if entry.etag.is_empty() {
+                cache.remove(url);
+            } else {
+                cache.insert(url.to_owned(), entry.clone());
+            }
+            return Ok(entry);
+        }
+    }
+
+    /// Starts downloading the source tarball of `git_ref`.
+    pub async fn archive(
+        &self,
+        owner: &str,
+        repo: &str,
+        git_ref: &str,
+    ) -> Result<reqwest::Response, anyhow::Error> {
+        Ok(self
+            .client
+            .get(&format!(
+                "{}/{}/{}/archive/{}.tar.gz",
+                self.web_url, owner, repo, git_ref
+            ))
+            .send()
+            .await?
+            .error_for_status()?)
+    }
+}
+
+/// Returns how long to wait when the response is a rate limit error.
+/// 403 is also used for other errors, so it's treated as a rate limit only when no requests remain.
+fn rate_limit_wait(resp: &reqwest::Response) -> Option<std::time::Duration> {
+    let header = |name: &str| {
+        resp.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok())
+    };
+    let status = resp.status();
+    let exhausted = header("x-ratelimit-remaining") == Some(0);
+    if status != reqwest::StatusCode::TOO_MANY_REQUESTS
+        && !(status == reqwest::StatusCode::FORBIDDEN && exhausted)
+    {
+        return None;
+    }
+    let wait = if let Some(retry_after) = header("retry-after") {
+        retry_after
+    } else if let Some(reset) = header("x-ratelimit-reset") {
+        let now = chrono::Utc::now().timestamp() as u64;
+        reset.saturating_sub(now) + 1
+    } else {
+        60
+    };
+    if wait > MAX_WAIT {
+        None
+    } else {
+        Some(std::time::Duration::from_secs(wait))
+    }
+}
+
+/// Extracts the URL of `rel="next"` from a Link header.
+fn next_link(link: &str) -> Option<String> {
+    link.split(',').find_map(|part| {
+        let mut params = part.split(';');
+        let url = params.next()?.trim();
+        if params.any(|param| param.trim() == "rel=\"next\"") {
+            Some(url.trim_start_matches('<').trim_end_matches('>').to_owned())
+        } else {
+            None
+        }
+    })
+}
@@ -0,0 +1,119 @@
+#[derive(Debug, serde::Deserialize)]
+pub struct Config {
+    pub name: String,
+    pub package_key: Option<String>,
+    pub repo_key: Option<String>,
+    pub srcdest: Option<String>,
+    pub logdest: Option<String>,
+    pub pkgbuild: Option<String>,
+    pub builds: std::collections::BTreeMap<String, BuildConfig>,
+    pub s3: Option<S3Config>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct BuildConfig {
+    pub chroot: Option<String>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct S3Config {
+    pub bucket: String,
+    pub region: String,
+    /// Public URL of the site served from the bucket, such as `http://arch.wanko.cc`.
+    pub url: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Repository {
+    pub dir: std::path::PathBuf,
+    pub config: Config,
+}
+
+impl Repository {
+    pub fn load<P>(dir: P) -> Result<Self, anyhow::Error>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        use anyhow::Context as _;
+
+        let dir = dir.as_ref();
+        let path = dir.join(".guzuta.yml");
+        let file = std::fs::File::open(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        let config = serde_yaml::from_reader(file)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        Ok(Self {
+            dir: dir.to_owned(),
+            config,
+        })
+    }
+
+    /// Finds every `*/.guzuta.yml` directly under `root`, ordered by directory name.
+    pub fn discover<P>(root: P) -> Result<Vec<Self>, anyhow::Error>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        let mut dirs = Vec::new();
+        for entry in std::fs::read_dir(root)? {
+            let path = entry?.path();
+            if path.join(".guzuta.yml").is_file() {
+                dirs.push(path);
+            }
+        }
+        dirs.sort();
+        dirs.into_iter().map(Self::load).collect()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    /// Directory where guzuta saves source files.
+    pub fn srcdest(&self) -> std::path::PathBuf {
+        self.dir
+            .join(self.config.srcdest.as_deref().unwrap_or("sources"))
+    }
+
+    /// Directory having a subdirectory with PKGBUILD for each package.
+    pub fn pkgbuild_dir(&self) -> std::path::PathBuf {
+        self.dir
+            .join(self.config.pkgbuild.as_deref().unwrap_or("PKGBUILDs"))
+    }
+
+    pub fn architectures(&self) -> impl Iterator<Item = &str> {
+        self.config.builds.keys().map(|arch| arch.as_str())
+    }
+
+    pub fn s3(&self) -> Result<&S3Config, anyhow::Error> {
+        self.config.s3.as_ref().ok_or_else(|| {
+            anyhow::anyhow!(
+                "s3 section is missing in {}",
+                self.dir.join(".guzuta.yml").display()
+            )
+        })
+    }
+
+    /// Returns `url` in the s3 section, without a trailing slash.
+    pub fn site_url(&self) -> Result<&str, anyhow::Error> {
+        self.s3()?
+            .url
+            .as_deref()
+            .map(|url| url.trim_end_matches('/'))
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "url is missing in the s3 section of {}",
+                    self.dir.join(".guzuta.yml").display()
+                )
+            })
+    }
+}
+
+impl S3Config {
+    pub fn client(&self) -> Result<rusoto_s3::S3Client, anyhow::Error> {
+        let region = self
+            .region
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid region {}: {}", self.region, e))?;
+        Ok(rusoto_s3::S3Client::new(region))
+    }
+}
@@ -0,0 +1,53 @@
+pub mod desc;
+pub mod github;
+pub mod guzuta;
+pub mod pages;
+pub mod pkgbuild;
+pub mod storage;
+pub mod upstream;
+
+/// Formats a size in bytes with binary prefixes, such as `1.5 MiB`.
+pub fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if size < 1024 {
+        return format!("{} B", size);
+    }
+    let mut size = size as f64 / 1024.0;
+    let mut unit = UNITS[0];
+    for u in &UNITS[1..] {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = u;
+    }
+    format!("{:.1} {}", size, unit)
+}
+
+/// Replaces the content of `path` by renaming a temporary file, so that readers see either the
+/// old content or the new one in whole.
+fn write_atomically(path: &std::path::Path, content: &[u8]) -> Result<(), anyhow::Error> {
+    use std::io::Write as _;
+
+    let temp_path = temporary_path(path);
+    let result = (|| {
+        let mut file = std::fs::File::create(&temp_path)?;
+        file.write_all(content)?;
+        file.sync_all()?;
+        std::fs::rename(&temp_path, path)
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    Ok(result?)
+}
+
+/// Returns `.<name>.part` in the same directory as `path`, so that it can be renamed atomically.
+fn temporary_path(path: &std::path::Path) -> std::path::PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.part", name))
+}
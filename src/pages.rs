@@ -0,0 +1,485 @@
+/// Number of entries kept in feed state objects and published in feeds.
+pub const FEED_ENTRIES: usize = 50;
+
+/// Stored as `<repo>/feed-state.json` so that builds replaced by newer ones stay in the feed.
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct FeedState {
+    pub entries: Vec<FeedEntry>,
+}
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct FeedEntry {
+    pub repository: String,
+    pub architecture: String,
+    pub name: String,
+    pub version: String,
+    pub desc: Option<String>,
+    pub builddate: i64,
+}
+
+impl FeedState {
+    pub async fn fetch(
+        storage: &dyn crate::storage::Storage,
+        repository: &str,
+    ) -> Result<Self, anyhow::Error> {
+        let key = format!("{}/feed-state.json", repository);
+        match storage.get(&key).await? {
+            Some(body) => Ok(serde_json::from_slice(&body)?),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Records builds which aren't in the state yet. `packages` yields each package in
+    /// `repository` with its architecture.
+    pub fn update<'a, I>(&mut self, repository: &str, packages: I)
+    where
+        I: IntoIterator<Item = (&'a str, &'a crate::desc::Desc)>,
+    {
+        for (architecture, desc) in packages {
+            let known = self.entries.iter().any(|entry| {
+                entry.architecture == architecture
+                    && entry.name == desc.name
+                    && entry.version == desc.version
+                    && entry.builddate == desc.builddate
+            });
+            if !known {
+                self.entries.push(FeedEntry {
+                    repository: repository.to_owned(),
+                    architecture: architecture.to_owned(),
+                    name: desc.name.clone(),
+                    version: desc.version.clone(),
+                    desc: desc.desc.clone(),
+                    builddate: desc.builddate,
+                });
+            }
+        }
+        sort_feed_entries(&mut self.entries);
+    }
+}
+
+/// Orders entries from the newest build and keeps `FEED_ENTRIES` of them.
+pub fn sort_feed_entries(entries: &mut Vec<FeedEntry>) {
+    entries.sort_by(|a, b| {
+        b.builddate
+            .cmp(&a.builddate)
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    entries.truncate(FEED_ENTRIES);
+}
+
+/// Strips version constraints (`foo>=1.0`) and optdepends reasons (`foo: for bar`).
+pub fn dependency_name(dependency: &str) -> &str {
+    match dependency.find(&['<', '>', '=', ':'][..]) {
+        Some(i) => dependency[..i].trim(),
+        None => dependency.trim(),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum UploadStatus {
+    Uploaded,
+    Skipped,
+    /// Unified diff against the stored object, returned instead of uploading in dry-run mode
+    Changed(String),
+}
+
+/// Uploads `body` unless the stored object already has the same content.
+pub async fn upload(
+    storage: &dyn crate::storage::Storage,
+    dry_run: bool,
+    key: &str,
+    content_type: &str,
+    body: String,
+) -> Result<UploadStatus, anyhow::Error> {
+    use anyhow::Context as _;
+    use md5::Digest as _;
+
+    if dry_run {
+        let remote_body = storage
+            .get(key)
+            .await
+            .with_context(|| format!("Failed to get {}", key))?;
+        let remote_body = remote_body.map(|b| String::from_utf8_lossy(&b).into_owned());
+        if remote_body.as_ref() == Some(&body) {
+            return Ok(UploadStatus::Skipped);
+        }
+        let old_header = if remote_body.is_some() {
+            format!("a/{}", key)
+        } else {
+            "/dev/null".to_owned()
+        };
+        let diff = similar::TextDiff::from_lines(remote_body.as_deref().unwrap_or(""), &body)
+            .unified_diff()
+            .header(&old_header, &format!("b/{}", key))
+            .to_string();
+        return Ok(UploadStatus::Changed(diff));
+    }
+
+    let md5 = format!("{:x}", md5::Md5::digest(body.as_bytes()));
+    let remote_md5 = storage
+        .md5(key)
+        .await
+        .with_context(|| format!("Failed to get MD5 of {}", key))?;
+    if remote_md5.as_ref() == Some(&md5) {
+        return Ok(UploadStatus::Skipped);
+    }
+    storage
+        .put(key, content_type, body.into_bytes())
+        .await
+        .with_context(|| format!("Failed to upload {}", key))?;
+    Ok(UploadStatus::Uploaded)
+}
+
+/// Renders the templates in `dir` in strict mode, so that a field missing from the input is an
+/// error rather than an empty string.
+pub fn templates<P>(dir: P) -> Result<handlebars::Handlebars<'static>, anyhow::Error>
+where
+    P: AsRef<std::path::Path>,
+{
+    let dir = dir.as_ref();
+    let mut handlebars = handlebars::Handlebars::new();
+    handlebars.set_strict_mode(true);
+    handlebars.register_template_file("index", dir.join("index.html"))?;
+    handlebars.register_template_file("repository", dir.join("repository.html"))?;
+    handlebars.register_template_file("package", dir.join("package.html"))?;
+    handlebars.register_template_file("files", dir.join("files.html"))?;
+    handlebars.register_template_file("feed", dir.join("feed.atom"))?;
+    Ok(handlebars)
+}
+
+/// Packages of a repository, rendered as `<repo>/index.html`.
+#[derive(serde::Serialize)]
+pub struct RepositoryInput {
+    pub name: String,
+    /// URL of the site serving the repository, for the `Server` line of pacman.conf.
+    pub url: String,
+    pub architectures: Vec<ArchitectureInput>,
+}
+#[derive(serde::Serialize)]
+pub struct ArchitectureInput {
+    pub name: String,
+    pub packages: Vec<PackageInput>,
+}
+#[derive(serde::Serialize)]
+pub struct PackageInput {
+    #[serde(flatten)]
+    pub desc: crate::desc::Desc,
+    pub builddate_timestamp: i64,
+    pub builddate_str: String,
+    pub csize_str: Option<String>,
+    pub isize_str: Option<String>,
+    pub files: Vec<String>,
+}
+
+impl ArchitectureInput {
+    /// Reads the packages in the `<repo>.db` database and their files in `<repo>.files`.
+    /// Packages are listed without files when `files_db` is `None`.
+    pub fn read(name: String, db: &[u8], files_db: Option<&[u8]>) -> Result<Self, anyhow::Error> {
+        let mut files = std::collections::HashMap::new();
+        if let Some(files_db) = files_db {
+            for entry in read_database(files_db)? {
+                if entry.filename == "files" {
+                    files.insert(entry.dir, crate::desc::parse_files(&entry.body));
+                }
+            }
+        }
+        let mut packages = Vec::new();
+        for entry in read_database(db)? {
+            if entry.filename == "desc" {
+                let mut package = parse_desc(&entry.body)?;
+                package.files = files.remove(&entry.dir).unwrap_or_default();
+                packages.push(package);
+            }
+        }
+        Ok(Self { name, packages })
+    }
+}
+
+/// Version of the `index.json` and `<repo>/packages.json` schema, documented in README.md.
+/// Bump it whenever a field is removed or changes its meaning.
+pub const JSON_SCHEMA_VERSION: u32 = 1;
+
+#[derive(serde::Serialize)]
+pub struct IndexJson<'a> {
+    pub schema_version: u32,
+    pub repositories: Vec<IndexRepositoryJson<'a>>,
+}
+#[derive(serde::Serialize)]
+pub struct IndexRepositoryJson<'a> {
+    pub name: &'a str,
+    pub packages_url: String,
+}
+
+impl<'a> IndexJson<'a> {
+    pub fn new(repositories: &'a [String]) -> Self {
+        Self {
+            schema_version: JSON_SCHEMA_VERSION,
+            repositories: repositories
+                .iter()
+                .map(|name| IndexRepositoryJson {
+                    name,
+                    packages_url: format!("/{}/packages.json", name),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct PackagesJson<'a> {
+    pub schema_version: u32,
+    pub repository: &'a str,
+    pub architectures: Vec<ArchitectureJson<'a>>,
+}
+#[derive(serde::Serialize)]
+pub struct ArchitectureJson<'a> {
+    pub name: &'a str,
+    pub packages: Vec<PackageJson<'a>>,
+}
+#[derive(serde::Serialize)]
+pub struct PackageJson<'a> {
+    #[serde(flatten)]
+    pub desc: &'a crate::desc::Desc,
+    pub files: &'a [String],
+}
+
+impl<'a> PackagesJson<'a> {
+    pub fn new(repository: &'a RepositoryInput) -> Self {
+        Self {
+            schema_version: JSON_SCHEMA_VERSION,
+            repository: &repository.name,
+            architectures: repository
+                .architectures
+                .iter()
+                .map(|arch| ArchitectureJson {
+                    name: &arch.name,
+                    packages: arch
+                        .packages
+                        .iter()
+                        .map(|package| PackageJson {
+                            desc: &package.desc,
+                            files: &package.files,
+                        })
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct FilesPageInput<'a> {
+    pub repository: &'a str,
+    pub architectures: Vec<FilesArchitectureInput<'a>>,
+}
+#[derive(serde::Serialize)]
+pub struct FilesArchitectureInput<'a> {
+    pub name: &'a str,
+    pub files: Vec<FileOwnerInput<'a>>,
+}
+#[derive(serde::Serialize)]
+pub struct FileOwnerInput<'a> {
+    pub path: &'a str,
+    pub package: &'a str,
+}
+
+impl RepositoryInput {
+    /// Builds the reverse index answering which package owns a file.
+    pub fn files_page(&self) -> FilesPageInput<'_> {
+        FilesPageInput {
+            repository: &self.name,
+            architectures: self
+                .architectures
+                .iter()
+                .map(|arch| {
+                    let mut files: Vec<_> = arch
+                        .packages
+                        .iter()
+                        .flat_map(|package| {
+                            package
+                                .files
+                                .iter()
+                                .filter(|path| !path.ends_with('/'))
+                                .map(move |path| FileOwnerInput {
+                                    path,
+                                    package: &package.desc.name,
+                                })
+                        })
+                        .collect();
+                    files.sort_by(|a, b| a.path.cmp(b.path));
+                    FilesArchitectureInput {
+                        name: &arch.name,
+                        files,
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct PackagePageInput<'a> {
+    pub repository: &'a str,
+    pub name: &'a str,
+    pub builds: Vec<PackageBuildInput<'a>>,
+}
+#[derive(serde::Serialize)]
+pub struct PackageBuildInput<'a> {
+    pub architecture: &'a str,
+    pub package: &'a PackageInput,
+    pub relations: Vec<RelationInput>,
+}
+#[derive(serde::Serialize)]
+pub struct RelationInput {
+    pub kind: &'static str,
+    pub entries: Vec<RelationEntryInput>,
+}
+#[derive(serde::Serialize)]
+pub struct RelationEntryInput {
+    pub text: String,
+    pub href: Option<String>,
+}
+
+impl RepositoryInput {
+    /// Groups the builds of each package across architectures, linking their dependencies with
+    /// `links`.
+    pub fn package_pages<'a>(&'a self, links: &PackageLinks) -> Vec<PackagePageInput<'a>> {
+        let mut pages: std::collections::BTreeMap<&str, Vec<PackageBuildInput>> =
+            std::collections::BTreeMap::new();
+        for arch in &self.architectures {
+            for package in &arch.packages {
+                let desc = &package.desc;
+                let relations = vec![
+                    ("depends", &desc.depends),
+                    ("optdepends", &desc.optdepends),
+                    ("makedepends", &desc.makedepends),
+                    ("checkdepends", &desc.checkdepends),
+                    ("provides", &desc.provides),
+                    ("conflicts", &desc.conflicts),
+                    ("replaces", &desc.replaces),
+                ]
+                .into_iter()
+                .filter(|(_, entries)| !entries.is_empty())
+                .map(|(kind, entries)| RelationInput {
+                    kind,
+                    entries: entries
+                        .iter()
+                        .map(|text| RelationEntryInput {
+                            text: text.to_owned(),
+                            href: links.href(dependency_name(text)),
+                        })
+                        .collect(),
+                })
+                .collect();
+                pages
+                    .entry(&desc.name)
+                    .or_default()
+                    .push(PackageBuildInput {
+                        architecture: &arch.name,
+                        package,
+                        relations,
+                    });
+            }
+        }
+        pages
+            .into_iter()
+            .map(|(name, builds)| PackagePageInput {
+                repository: &self.name,
+                name,
+                builds,
+            })
+            .collect()
+    }
+}
+
+/// Maps package names and provided names to their package pages across all repositories.
+pub struct PackageLinks {
+    hrefs: std::collections::HashMap<String, String>,
+}
+
+impl PackageLinks {
+    pub fn new<'a, I>(repositories: I) -> Self
+    where
+        I: IntoIterator<Item = &'a RepositoryInput>,
+    {
+        let mut hrefs = std::collections::HashMap::new();
+        let mut provides = Vec::new();
+        for repository in repositories {
+            for arch in &repository.architectures {
+                for package in &arch.packages {
+                    let href = format!("/{}/packages/{}/", repository.name, package.desc.name);
+                    hrefs.insert(package.desc.name.clone(), href.clone());
+                    for provide in &package.desc.provides {
+                        provides.push((dependency_name(provide).to_owned(), href.clone()));
+                    }
+                }
+            }
+        }
+        for (name, href) in provides {
+            hrefs.entry(name).or_insert(href);
+        }
+        Self { hrefs }
+    }
+
+    pub fn href(&self, name: &str) -> Option<String> {
+        self.hrefs.get(name).cloned()
+    }
+}
+
+fn parse_desc(body: &str) -> Result<PackageInput, anyhow::Error> {
+    let desc: crate::desc::Desc = body.parse()?;
+    Ok(PackageInput {
+        builddate_timestamp: desc.builddate,
+        builddate_str: format_timestamp(desc.builddate),
+        csize_str: desc.csize.map(crate::format_size),
+        isize_str: desc.isize.map(crate::format_size),
+        files: Vec::new(),
+        desc,
+    })
+}
+
+/// Formats a Unix timestamp in RFC 3339, as `builddate_str` and Atom feeds do.
+pub fn format_timestamp(timestamp: i64) -> String {
+    chrono::DateTime::<chrono::Utc>::from_utc(
+        chrono::NaiveDateTime::from_timestamp(timestamp, 0),
+        chrono::Utc,
+    )
+    .to_rfc3339()
+}
+
+struct DatabaseEntry {
+    dir: String,
+    filename: String,
+    body: String,
+}
+
+/// Reads every file in a gzipped repository database such as `<repo>.db` or `<repo>.files`.
+fn read_database(body: &[u8]) -> Result<Vec<DatabaseEntry>, anyhow::Error> {
+    use std::io::Read as _;
+
+    let gz_reader = flate2::read::GzDecoder::new(body);
+    let mut tar_reader = tar::Archive::new(gz_reader);
+    let mut entries = Vec::new();
+    for entry in tar_reader.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path()?.into_owned();
+        let filename = match path.file_name().and_then(|f| f.to_str()) {
+            Some(filename) => filename.to_owned(),
+            None => continue,
+        };
+        let dir = path
+            .parent()
+            .map(|p| p.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut body = String::new();
+        entry.read_to_string(&mut body)?;
+        entries.push(DatabaseEntry {
+            dir,
+            filename,
+            body,
+        });
+    }
+    Ok(entries)
+}
@@ -0,0 +1,1121 @@
+/// A PKGBUILD kept as its original text, with the positions of its top-level variable
+/// assignments so that their values can be rewritten without touching anything else.
+#[derive(Debug, Clone)]
+pub struct Pkgbuild {
+    text: String,
+    assignments: Vec<Assignment>,
+    /// Byte ranges of top-level commands other than assignments and function definitions.
+    commands: Vec<std::ops::Range<usize>>,
+}
+
+#[derive(Debug, Clone)]
+struct Assignment {
+    name: String,
+    /// True for `name+=value`, which appends to the variable.
+    append: bool,
+    /// Byte range of the value following `=`, including parentheses of arrays.
+    value: std::ops::Range<usize>,
+    /// Byte ranges of each element when the value is an array.
+    elements: Option<Vec<std::ops::Range<usize>>>,
+}
+
+impl std::str::FromStr for Pkgbuild {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (assignments, commands) = Scanner::new(text).scan()?;
+        Ok(Self {
+            text: text.to_owned(),
+            assignments,
+            commands,
+        })
+    }
+}
+
+impl std::fmt::Display for Pkgbuild {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl Pkgbuild {
+    /// Returns the value of the last top-level assignment to `name` with quotes removed.
+    /// Parameter expansions are left as they are.
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.find(name)
+            .map(|assignment| unquote(&self.text[assignment.value.clone()]))
+    }
+
+    /// Returns the elements of the last top-level array assignment to `name` with quotes removed.
+    pub fn get_array(&self, name: &str) -> Option<Vec<String>> {
+        self.find(name).and_then(|assignment| {
+            assignment.elements.as_ref().map(|elements| {
+                elements
+                    .iter()
+                    .map(|element| unquote(&self.text[element.clone()]))
+                    .collect()
+            })
+        })
+    }
+
+    /// Replaces the value of the last top-level assignment to `name`, keeping its quoting style.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), anyhow::Error> {
+        let range = self
+            .find(name)
+            .ok_or_else(|| anyhow::anyhow!("{} is not assigned in PKGBUILD", name))?
+            .value
+            .clone();
+        self.replace(range, value)
+    }
+
+    /// Replaces the `index`-th element of the last top-level array assignment to `name`,
+    /// keeping its quoting style.
+    pub fn set_element(
+        &mut self,
+        name: &str,
+        index: usize,
+        value: &str,
+    ) -> Result<(), anyhow::Error> {
+        let range = self
+            .find(name)
+            .ok_or_else(|| anyhow::anyhow!("{} is not assigned in PKGBUILD", name))?
+            .elements
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("{} is not an array in PKGBUILD", name))?
+            .get(index)
+            .ok_or_else(|| anyhow::anyhow!("{} has no element at {}", name, index))?
+            .clone();
+        self.replace(range, value)
+    }
+
+    /// Evaluates the top-level assignments in order, like sourcing the PKGBUILD in bash but
+    /// without running any commands. `$var`, `${var}`, array subscripts, `$'...'` and parameter
+    /// expansions such as `${var/pattern/replacement}`, `${var%pattern}` and `${var:-default}`
+    /// are supported, and `+=` appends to the variable.
+    pub fn evaluate(&self) -> Variables {
+        let mut variables = Variables::default();
+        for assignment in &self.assignments {
+            let value = match assignment.elements {
+                Some(ref elements) => elements
+                    .iter()
+                    .map(|element| variables.expand_word(&self.text[element.clone()]))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(|fields| Value::Array(fields.into_iter().flatten().collect())),
+                None => variables
+                    .expand_word(&self.text[assignment.value.clone()])
+                    .map(|fields| Value::Scalar(fields.join(" "))),
+            };
+            let value = value.map_err(|e| format!("Failed to evaluate {}: {}", assignment.name, e));
+            let value = match variables.values.remove(&assignment.name) {
+                Some(old) if assignment.append => append(old, value),
+                _ => value,
+            };
+            variables.values.insert(assignment.name.clone(), value);
+        }
+        variables
+    }
+
+    /// Returns the top-level commands which are neither assignments nor function definitions,
+    /// such as `if` or `case` blocks. Assignments inside them aren't evaluated.
+    pub fn commands(&self) -> impl Iterator<Item = &str> {
+        self.commands
+            .iter()
+            .map(move |range| &self.text[range.clone()])
+    }
+
+    /// Returns the last assignment to `name` which isn't an append.
+    fn find(&self, name: &str) -> Option<&Assignment> {
+        self.assignments
+            .iter()
+            .rev()
+            .find(|a| a.name == name && !a.append)
+    }
+
+    fn replace(&mut self, range: std::ops::Range<usize>, value: &str) -> Result<(), anyhow::Error> {
+        let quoted = quote_like(&self.text[range.clone()], value);
+        self.text.replace_range(range, &quoted);
+        let (assignments, commands) = Scanner::new(&self.text).scan()?;
+        self.assignments = assignments;
+        self.commands = commands;
+        Ok(())
+    }
+}
+
+/// Appends `value` to `old` like `+=` in bash. Appending a scalar to an array appends it to the
+/// first element.
+fn append(old: Result<Value, String>, value: Result<Value, String>) -> Result<Value, String> {
+    Ok(match (old?, value?) {
+        (Value::Scalar(old), Value::Scalar(value)) => Value::Scalar(old + &value),
+        (Value::Scalar(old), Value::Array(elements)) => {
+            Value::Array(std::iter::once(old).chain(elements).collect())
+        }
+        (Value::Array(mut old), Value::Scalar(value)) => {
+            match old.first_mut() {
+                Some(first) => first.push_str(&value),
+                None => old.push(value),
+            }
+            Value::Array(old)
+        }
+        (Value::Array(mut old), Value::Array(elements)) => {
+            old.extend(elements);
+            Value::Array(old)
+        }
+    })
+}
+
+/// Value of a variable evaluated by `Pkgbuild::evaluate`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Scalar(String),
+    Array(Vec<String>),
+}
+
+/// Top-level variables of a PKGBUILD, evaluated without running bash.
+/// Variables which can't be evaluated, such as the ones using command substitution, keep the
+/// reason so that reading them fails.
+#[derive(Debug, Clone, Default)]
+pub struct Variables {
+    values: std::collections::BTreeMap<String, Result<Value, String>>,
+}
+
+impl Variables {
+    /// Returns the value of `name` as a string. The first element is returned for arrays like
+    /// bash, and unset variables are empty.
+    pub fn get(&self, name: &str) -> Result<String, anyhow::Error> {
+        match self.values.get(name) {
+            None => Ok(String::new()),
+            Some(Ok(Value::Scalar(value))) => Ok(value.clone()),
+            Some(Ok(Value::Array(elements))) => Ok(elements.first().cloned().unwrap_or_default()),
+            Some(Err(e)) => Err(anyhow::anyhow!("{}", e)),
+        }
+    }
+
+    /// Returns the elements of `name`. A scalar is returned as an array having one element.
+    pub fn get_array(&self, name: &str) -> Result<Vec<String>, anyhow::Error> {
+        match self.values.get(name) {
+            None => Ok(Vec::new()),
+            Some(Ok(Value::Scalar(value))) => Ok(vec![value.clone()]),
+            Some(Ok(Value::Array(elements))) => Ok(elements.clone()),
+            Some(Err(e)) => Err(anyhow::anyhow!("{}", e)),
+        }
+    }
+
+    /// Returns the names of the variables, including the ones which can't be evaluated.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.values.keys().map(String::as_str)
+    }
+
+    /// Returns `[epoch:]pkgver-pkgrel` as pacman shows.
+    pub fn full_version(&self) -> Result<String, anyhow::Error> {
+        let epoch = self.get("epoch")?;
+        let version = format!("{}-{}", self.get("pkgver")?, self.get("pkgrel")?);
+        if epoch.is_empty() || epoch == "0" {
+            Ok(version)
+        } else {
+            Ok(format!("{}:{}", epoch, version))
+        }
+    }
+
+    fn is_set(&self, name: &str) -> bool {
+        self.values.contains_key(name)
+    }
+
+    /// Expands a shell word into fields. Only `"${name[@]}"` yields multiple fields.
+    fn expand_word(&self, word: &str) -> Result<Vec<String>, anyhow::Error> {
+        let inner = word
+            .strip_prefix('"')
+            .and_then(|w| w.strip_suffix('"'))
+            .unwrap_or(word);
+        if let Some(name) = inner
+            .strip_prefix("${")
+            .and_then(|w| w.strip_suffix("[@]}").or_else(|| w.strip_suffix("[*]}")))
+        {
+            if is_name(name) {
+                return self.get_array(name);
+            }
+        }
+
+        let mut s = String::new();
+        let mut chars = word.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\'' => {
+                    for c in &mut chars {
+                        if c == '\'' {
+                            break;
+                        }
+                        s.push(c);
+                    }
+                }
+                '"' => {
+                    while let Some(c) = chars.next() {
+                        match c {
+                            '"' => break,
+                            '\\' => match chars.peek() {
+                                Some('\n') => {
+                                    chars.next();
+                                }
+                                Some(&n) if "\"\\$`".contains(n) => {
+                                    s.push(n);
+                                    chars.next();
+                                }
+                                _ => s.push(c),
+                            },
+                            '$' => s.push_str(&self.expand_dollar(&mut chars)?),
+                            '`' => return Err(command_substitution()),
+                            _ => s.push(c),
+                        }
+                    }
+                }
+                '\\' => match chars.next() {
+                    Some('\n') | None => {}
+                    Some(n) => s.push(n),
+                },
+                '$' if chars.peek() == Some(&'\'') => {
+                    chars.next();
+                    s.push_str(&ansi_c_quote(&mut chars)?);
+                }
+                '$' => s.push_str(&self.expand_dollar(&mut chars)?),
+                '`' => return Err(command_substitution()),
+                _ => s.push(c),
+            }
+        }
+        Ok(vec![s])
+    }
+
+    /// Expands a parameter following `$`.
+    fn expand_dollar(
+        &self,
+        chars: &mut std::iter::Peekable<std::str::Chars<'_>>,
+    ) -> Result<String, anyhow::Error> {
+        match chars.peek() {
+            Some('(') => Err(command_substitution()),
+            Some('{') => {
+                chars.next();
+                let mut expr = String::new();
+                let mut depth = 1;
+                for c in &mut *chars {
+                    match c {
+                        '{' => depth += 1,
+                        '}' => {
+                            depth -= 1;
+                            if depth == 0 {
+                                return self.expand_parameter(&expr);
+                            }
+                        }
+                        _ => {}
+                    }
+                    expr.push(c);
+                }
+                Err(anyhow::anyhow!("Unterminated ${{{}", expr))
+            }
+            Some(&c) if c == '_' || c.is_ascii_alphabetic() => {
+                let mut name = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c == '_' || c.is_ascii_alphanumeric() {
+                        name.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                self.get(&name)
+            }
+            _ => Ok("$".to_owned()),
+        }
+    }
+
+    /// Expands the content of `${...}`.
+    fn expand_parameter(&self, expr: &str) -> Result<String, anyhow::Error> {
+        if let Some(name) = expr.strip_prefix('#') {
+            if is_name(name) {
+                return Ok(self.get(name)?.chars().count().to_string());
+            }
+        }
+        let name_len = expr
+            .find(|c: char| !(c == '_' || c.is_ascii_alphanumeric()))
+            .unwrap_or(expr.len());
+        let name = &expr[..name_len];
+        if !is_name(name) {
+            return Err(anyhow::anyhow!(
+                "Unsupported parameter expansion ${{{}}}",
+                expr
+            ));
+        }
+        let mut rest = &expr[name_len..];
+        let value = if let Some(r) = rest.strip_prefix('[') {
+            let end = r
+                .find(']')
+                .ok_or_else(|| anyhow::anyhow!("Unterminated subscript in ${{{}}}", expr))?;
+            let index = &r[..end];
+            rest = &r[end + 1..];
+            let elements = self.get_array(name)?;
+            match index {
+                "@" | "*" => elements.join(" "),
+                _ => {
+                    let index: usize = index
+                        .parse()
+                        .map_err(|_| anyhow::anyhow!("Unsupported subscript in ${{{}}}", expr))?;
+                    elements.get(index).cloned().unwrap_or_default()
+                }
+            }
+        } else {
+            self.get(name)?
+        };
+
+        let operand =
+            |s: &str| -> Result<String, anyhow::Error> { Ok(self.expand_word(s)?.join(" ")) };
+        if rest.is_empty() {
+            Ok(value)
+        } else if let Some(r) = rest.strip_prefix(":-") {
+            if value.is_empty() {
+                operand(r)
+            } else {
+                Ok(value)
+            }
+        } else if let Some(r) = rest.strip_prefix('-') {
+            if self.is_set(name) {
+                Ok(value)
+            } else {
+                operand(r)
+            }
+        } else if let Some(r) = rest.strip_prefix("##") {
+            Ok(remove_prefix(&value, &Glob::new(&operand(r)?)?, true))
+        } else if let Some(r) = rest.strip_prefix('#') {
+            Ok(remove_prefix(&value, &Glob::new(&operand(r)?)?, false))
+        } else if let Some(r) = rest.strip_prefix("%%") {
+            Ok(remove_suffix(&value, &Glob::new(&operand(r)?)?, true))
+        } else if let Some(r) = rest.strip_prefix('%') {
+            Ok(remove_suffix(&value, &Glob::new(&operand(r)?)?, false))
+        } else if let Some(r) = rest.strip_prefix('/') {
+            let (all, r) = match r.strip_prefix('/') {
+                Some(r) => (true, r),
+                None => (false, r),
+            };
+            let (pattern, replacement) = match r.find('/') {
+                Some(i) => (&r[..i], &r[i + 1..]),
+                None => (r, ""),
+            };
+            Ok(replace(
+                &value,
+                &Glob::new(&operand(pattern)?)?,
+                &operand(replacement)?,
+                all,
+            ))
+        } else if rest == ",," {
+            Ok(value.to_lowercase())
+        } else if rest == "^^" {
+            Ok(value.to_uppercase())
+        } else {
+            Err(anyhow::anyhow!(
+                "Unsupported parameter expansion ${{{}}}",
+                expr
+            ))
+        }
+    }
+}
+
+/// Decodes the content of `$'...'` following the opening quote.
+fn ansi_c_quote(
+    chars: &mut std::iter::Peekable<std::str::Chars<'_>>,
+) -> Result<String, anyhow::Error> {
+    let mut s = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => return Ok(s),
+            '\\' => {
+                let c = chars
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("Unterminated $'"))?;
+                match c {
+                    'a' => s.push('\x07'),
+                    'b' => s.push('\x08'),
+                    'e' | 'E' => s.push('\x1b'),
+                    'f' => s.push('\x0c'),
+                    'n' => s.push('\n'),
+                    'r' => s.push('\r'),
+                    't' => s.push('\t'),
+                    'v' => s.push('\x0b'),
+                    '\\' | '\'' | '"' | '?' => s.push(c),
+                    '0'..='7' => {
+                        let mut code = c.to_digit(8).unwrap();
+                        for _ in 0..2 {
+                            match chars.peek().and_then(|c| c.to_digit(8)) {
+                                Some(d) => {
+                                    code = code * 8 + d;
+                                    chars.next();
+                                }
+                                None => break,
+                            }
+                        }
+                        s.push(code_point(code)?);
+                    }
+                    'x' | 'u' | 'U' => {
+                        let max = match c {
+                            'x' => 2,
+                            'u' => 4,
+                            _ => 8,
+                        };
+                        let mut code = 0;
+                        let mut digits = 0;
+                        while digits < max {
+                            match chars.peek().and_then(|c| c.to_digit(16)) {
+                                Some(d) => {
+                                    code = code * 16 + d;
+                                    digits += 1;
+                                    chars.next();
+                                }
+                                None => break,
+                            }
+                        }
+                        if digits == 0 {
+                            s.push('\\');
+                            s.push(c);
+                        } else {
+                            s.push(code_point(code)?);
+                        }
+                    }
+                    'c' => return Err(anyhow::anyhow!("Unsupported escape \\c in $'")),
+                    _ => {
+                        s.push('\\');
+                        s.push(c);
+                    }
+                }
+            }
+            _ => s.push(c),
+        }
+    }
+    Err(anyhow::anyhow!("Unterminated $'"))
+}
+
+fn code_point(code: u32) -> Result<char, anyhow::Error> {
+    // Bytes above 0x7f can't be represented in a String, nor NUL in bash
+    match std::char::from_u32(code) {
+        Some(c) if c != '\0' => Ok(c),
+        _ => Err(anyhow::anyhow!("Unsupported character {:#x} in $'", code)),
+    }
+}
+
+fn command_substitution() -> anyhow::Error {
+    anyhow::anyhow!("Command substitution is not allowed")
+}
+
+fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c == '_' || c.is_ascii_alphabetic())
+        && chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+/// Shell pattern supporting `*` and `?`.
+struct Glob {
+    regex: regex::Regex,
+    empty: bool,
+}
+
+impl Glob {
+    fn new(pattern: &str) -> Result<Self, anyhow::Error> {
+        let mut re = String::from(r"\A(?s:");
+        for c in pattern.chars() {
+            match c {
+                '*' => re.push_str(".*"),
+                '?' => re.push('.'),
+                '[' => {
+                    return Err(anyhow::anyhow!(
+                        "Bracket expressions are not supported in {}",
+                        pattern
+                    ))
+                }
+                _ => re.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        re.push_str(r")\z");
+        Ok(Self {
+            regex: regex::Regex::new(&re)?,
+            empty: pattern.is_empty(),
+        })
+    }
+
+    fn matches(&self, s: &str) -> bool {
+        self.regex.is_match(s)
+    }
+}
+
+fn boundaries(s: &str) -> Vec<usize> {
+    s.char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(s.len()))
+        .collect()
+}
+
+fn remove_prefix(value: &str, glob: &Glob, longest: bool) -> String {
+    let mut ends = boundaries(value);
+    if longest {
+        ends.reverse();
+    }
+    match ends.into_iter().find(|&i| glob.matches(&value[..i])) {
+        Some(i) => value[i..].to_owned(),
+        None => value.to_owned(),
+    }
+}
+
+fn remove_suffix(value: &str, glob: &Glob, longest: bool) -> String {
+    let mut starts = boundaries(value);
+    if !longest {
+        starts.reverse();
+    }
+    match starts.into_iter().find(|&i| glob.matches(&value[i..])) {
+        Some(i) => value[..i].to_owned(),
+        None => value.to_owned(),
+    }
+}
+
+/// Replaces the longest match of `glob` at the leftmost position, or every match when `all`.
+fn replace(value: &str, glob: &Glob, replacement: &str, all: bool) -> String {
+    if glob.empty {
+        return value.to_owned();
+    }
+    let bounds = boundaries(value);
+    let mut result = String::new();
+    let mut k = 0;
+    let mut replaced = false;
+    while k < bounds.len() {
+        let start = bounds[k];
+        let end = if replaced && !all {
+            None
+        } else {
+            bounds[k + 1..]
+                .iter()
+                .rev()
+                .position(|&end| glob.matches(&value[start..end]))
+                .map(|p| bounds.len() - 1 - p)
+        };
+        match end {
+            Some(j) => {
+                result.push_str(replacement);
+                replaced = true;
+                k = j;
+            }
+            None => {
+                if let Some(&next) = bounds.get(k + 1) {
+                    result.push_str(&value[start..next]);
+                }
+                k += 1;
+            }
+        }
+    }
+    result
+}
+
+/// Quotes `value` in the same style as `old`.
+fn quote_like(old: &str, value: &str) -> String {
+    let is_bare = |s: &str| {
+        !s.is_empty()
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || "._+-:/@%,=".contains(c))
+    };
+    if old.starts_with('"') {
+        let mut quoted = String::from("\"");
+        for c in value.chars() {
+            if "\"\\$`".contains(c) {
+                quoted.push('\\');
+            }
+            quoted.push(c);
+        }
+        quoted.push('"');
+        quoted
+    } else if old.starts_with('\'') || !is_bare(value) {
+        format!("'{}'", value.replace('\'', r#"'\''"#))
+    } else {
+        value.to_owned()
+    }
+}
+
+/// Removes quotes and backslash escapes from a shell word.
+fn unquote(word: &str) -> String {
+    let mut s = String::new();
+    let mut chars = word.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                for c in &mut chars {
+                    if c == '\'' {
+                        break;
+                    }
+                    s.push(c);
+                }
+            }
+            '"' => {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => match chars.peek() {
+                            Some(&n) if "\"\\$`".contains(n) => {
+                                s.push(n);
+                                chars.next();
+                            }
+                            _ => s.push(c),
+                        },
+                        _ => s.push(c),
+                    }
+                }
+            }
+            '\\' => {
+                if let Some(n) = chars.next() {
+                    s.push(n);
+                }
+            }
+            _ => s.push(c),
+        }
+    }
+    s
+}
+
+/// A minimal shell lexer which knows enough to skip over function bodies, quotes, comments,
+/// command substitutions and here-documents.
+struct Scanner<'a> {
+    text: &'a str,
+    bytes: &'a [u8],
+    pos: usize,
+    heredocs: Vec<(String, bool)>,
+}
+
+impl<'a> Scanner<'a> {
+    fn new(text: &'a str) -> Self {
+        Self {
+            text,
+            bytes: text.as_bytes(),
+            pos: 0,
+            heredocs: Vec::new(),
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<u8> {
+        self.bytes.get(self.pos + offset).copied()
+    }
+
+    fn error(&self, message: &str) -> anyhow::Error {
+        let line = self.text[..self.pos.min(self.text.len())]
+            .matches('\n')
+            .count()
+            + 1;
+        anyhow::anyhow!("{} at line {} of PKGBUILD", message, line)
+    }
+
+    /// Returns the top-level assignments and the ranges of the other commands except function
+    /// definitions.
+    fn scan(mut self) -> Result<(Vec<Assignment>, Vec<std::ops::Range<usize>>), anyhow::Error> {
+        let mut assignments = Vec::new();
+        let mut commands = Vec::new();
+        // Whether the last command was a function definition, whose body may follow on the next line
+        let mut function = false;
+        loop {
+            self.skip_blanks(true)?;
+            match self.peek() {
+                None => return Ok((assignments, commands)),
+                Some(b'#') => self.skip_comment(),
+                Some(b';') => self.pos += 1,
+                Some(_) => {
+                    if let Some(assignment) = self.assignment()? {
+                        assignments.push(assignment);
+                        function = false;
+                    } else {
+                        let start = self.pos;
+                        self.skip_command()?;
+                        let command = &self.text[start..self.pos];
+                        // The body may start on the line after `name()`
+                        let body = function && command.starts_with('{');
+                        function = is_function_definition(command);
+                        if !function && !body {
+                            commands.push(start..self.pos);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Skips spaces and tabs, and also newlines when `newlines` is true.
+    /// Pending here-documents are consumed at the end of their line.
+    fn skip_blanks(&mut self, newlines: bool) -> Result<(), anyhow::Error> {
+        while let Some(c) = self.peek() {
+            match c {
+                b' ' | b'\t' => self.pos += 1,
+                b'\\' if self.peek_at(1) == Some(b'\n') => self.pos += 2,
+                b'\n' if newlines => self.newline()?,
+                _ => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn newline(&mut self) -> Result<(), anyhow::Error> {
+        self.pos += 1;
+        for (delimiter, strip_tabs) in std::mem::take(&mut self.heredocs) {
+            loop {
+                if self.pos >= self.bytes.len() {
+                    return Err(self.error(&format!("Unterminated here-document {}", delimiter)));
+                }
+                let end = self.text[self.pos..]
+                    .find('\n')
+                    .map(|i| self.pos + i)
+                    .unwrap_or_else(|| self.bytes.len());
+                let line = &self.text[self.pos..end];
+                let line = if strip_tabs {
+                    line.trim_start_matches('\t')
+                } else {
+                    line
+                };
+                self.pos = (end + 1).min(self.bytes.len());
+                if line == delimiter {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn skip_comment(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == b'\n' {
+                break;
+            }
+            self.pos += 1;
+        }
+    }
+
+    /// Parses `name=value` at the current position, or returns `None` leaving the position as is.
+    fn assignment(&mut self) -> Result<Option<Assignment>, anyhow::Error> {
+        let start = self.pos;
+        let mut end = start;
+        while let Some(&c) = self.bytes.get(end) {
+            if c == b'_' || c.is_ascii_alphabetic() || (end > start && c.is_ascii_digit()) {
+                end += 1;
+            } else {
+                break;
+            }
+        }
+        let append = self.bytes.get(end) == Some(&b'+');
+        let op_len = if append { 2 } else { 1 };
+        if end == start || self.bytes.get(end + op_len - 1) != Some(&b'=') {
+            return Ok(None);
+        }
+        let name = self.text[start..end].to_owned();
+        self.pos = end + op_len;
+        let value_start = self.pos;
+        let elements = if self.peek() == Some(b'(') {
+            self.pos += 1;
+            let mut elements = Vec::new();
+            loop {
+                self.skip_blanks(true)?;
+                match self.peek() {
+                    None => return Err(self.error(&format!("Unterminated array {}", name))),
+                    Some(b')') => {
+                        self.pos += 1;
+                        break;
+                    }
+                    Some(b'#') => self.skip_comment(),
+                    Some(_) => {
+                        let element_start = self.pos;
+                        self.skip_word()?;
+                        if self.pos == element_start {
+                            return Err(self.error(&format!("Unexpected character in {}", name)));
+                        }
+                        elements.push(element_start..self.pos);
+                    }
+                }
+            }
+            Some(elements)
+        } else {
+            self.skip_word()?;
+            None
+        };
+        Ok(Some(Assignment {
+            name,
+            append,
+            value: value_start..self.pos,
+            elements,
+        }))
+    }
+
+    /// Skips a shell word, stopping at unquoted blanks and operators.
+    fn skip_word(&mut self) -> Result<(), anyhow::Error> {
+        while let Some(c) = self.peek() {
+            match c {
+                b' ' | b'\t' | b'\n' | b';' | b'&' | b'|' | b'(' | b')' | b'<' | b'>' => break,
+                _ => self.skip_char()?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Skips a character, or a whole quoted string or expansion starting at it.
+    fn skip_char(&mut self) -> Result<(), anyhow::Error> {
+        match self.peek() {
+            Some(b'\'') => {
+                let start = self.pos;
+                self.pos += 1;
+                match self.text[self.pos..].find('\'') {
+                    Some(i) => self.pos += i + 1,
+                    None => {
+                        self.pos = start;
+                        return Err(self.error("Unterminated single quote"));
+                    }
+                }
+            }
+            Some(b'"') => {
+                let start = self.pos;
+                self.pos += 1;
+                loop {
+                    match self.peek() {
+                        None => {
+                            self.pos = start;
+                            return Err(self.error("Unterminated double quote"));
+                        }
+                        Some(b'"') => {
+                            self.pos += 1;
+                            break;
+                        }
+                        Some(b'\\') => self.skip_escape(),
+                        // `$'` isn't quoting inside double quotes
+                        Some(b'$') if self.peek_at(1) == Some(b'\'') => self.pos += 1,
+                        Some(b'$') | Some(b'`') => self.skip_char()?,
+                        Some(_) => self.pos += 1,
+                    }
+                }
+            }
+            Some(b'\\') => self.skip_escape(),
+            Some(b'`') => {
+                let start = self.pos;
+                self.pos += 1;
+                loop {
+                    match self.peek() {
+                        None => {
+                            self.pos = start;
+                            return Err(self.error("Unterminated backquote"));
+                        }
+                        Some(b'`') => {
+                            self.pos += 1;
+                            break;
+                        }
+                        Some(b'\\') => self.skip_escape(),
+                        Some(_) => self.pos += 1,
+                    }
+                }
+            }
+            Some(b'$') => match self.peek_at(1) {
+                Some(b'\'') => {
+                    let start = self.pos;
+                    self.pos += 2;
+                    loop {
+                        match self.peek() {
+                            None => {
+                                self.pos = start;
+                                return Err(self.error("Unterminated $'"));
+                            }
+                            Some(b'\'') => {
+                                self.pos += 1;
+                                break;
+                            }
+                            Some(b'\\') => self.skip_escape(),
+                            Some(_) => self.pos += 1,
+                        }
+                    }
+                }
+                Some(b'{') => {
+                    self.pos += 2;
+                    self.skip_until(b'}')?;
+                }
+                Some(b'(') => {
+                    self.pos += 2;
+                    self.skip_until(b')')?;
+                }
+                _ => self.pos += 1,
+            },
+            Some(c) => self.pos += utf8_len(c),
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Skips a backslash and the character it escapes, which may be missing at the end of the
+    /// text.
+    fn skip_escape(&mut self) {
+        self.pos += 1;
+        if let Some(c) = self.peek() {
+            self.pos += utf8_len(c);
+        }
+    }
+
+    /// Skips to the `close` which balances an already consumed opening bracket.
+    fn skip_until(&mut self, close: u8) -> Result<(), anyhow::Error> {
+        let open = if close == b'}' { b'{' } else { b'(' };
+        let start = self.pos;
+        let mut depth = 1;
+        loop {
+            match self.peek() {
+                None => {
+                    self.pos = start;
+                    return Err(self.error(&format!("Unterminated {}", open as char)));
+                }
+                Some(c) if c == close => {
+                    self.pos += 1;
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                }
+                Some(c) if c == open => {
+                    self.pos += 1;
+                    depth += 1;
+                }
+                Some(_) => self.skip_char()?,
+            }
+        }
+    }
+
+    /// Skips a command up to the end of its line. Compound commands such as function
+    /// definitions, `if`, `case`, `for`, `while` and `until` are skipped as a whole, so that
+    /// assignments inside them are never taken as top-level ones.
+    fn skip_command(&mut self) -> Result<(), anyhow::Error> {
+        let start = self.pos;
+        // Words closing the compound commands being skipped, innermost last
+        let mut blocks: Vec<&'static str> = Vec::new();
+        let mut command_position = true;
+        while let Some(c) = self.peek() {
+            match c {
+                b'\n' => {
+                    if blocks.is_empty() {
+                        return Ok(());
+                    }
+                    self.newline()?;
+                    command_position = true;
+                }
+                b' ' | b'\t' => self.pos += 1,
+                b'\\' if self.peek_at(1) == Some(b'\n') => self.pos += 2,
+                b'#' => self.skip_comment(),
+                b';' | b'&' | b'|' => {
+                    self.pos += 1;
+                    command_position = true;
+                }
+                b'(' => {
+                    // Elements of `name=(...)` are words rather than commands
+                    let array = self.pos > 0 && self.bytes[self.pos - 1] == b'=';
+                    blocks.push(")");
+                    self.pos += 1;
+                    command_position = !array;
+                }
+                b')' => {
+                    // Patterns of `case` end with an unbalanced `)`
+                    if blocks.last() == Some(&")") {
+                        blocks.pop();
+                    }
+                    self.pos += 1;
+                    command_position = true;
+                }
+                b'<' if self.peek_at(1) == Some(b'<') && self.peek_at(2) == Some(b'<') => {
+                    self.pos += 3;
+                    command_position = false;
+                }
+                b'<' if self.peek_at(1) == Some(b'<') => {
+                    self.pos += 2;
+                    self.heredoc()?;
+                    command_position = false;
+                }
+                b'<' | b'>' => {
+                    self.pos += 1;
+                    command_position = false;
+                }
+                _ => {
+                    let word_start = self.pos;
+                    self.skip_word()?;
+                    let word = &self.text[word_start..self.pos];
+                    let keyword = if command_position { word } else { "" };
+                    command_position = false;
+                    match (word, keyword) {
+                        (_, "{") => {
+                            blocks.push("}");
+                            command_position = true;
+                        }
+                        (_, "}") | (_, "fi") | (_, "esac") | (_, "done") => {
+                            if blocks.last() == Some(&word) {
+                                blocks.pop();
+                            } else {
+                                self.pos = word_start;
+                                return Err(self.error(&format!("Unexpected {}", word)));
+                            }
+                        }
+                        (_, "if") => {
+                            blocks.push("fi");
+                            command_position = true;
+                        }
+                        (_, "case") => blocks.push("esac"),
+                        (_, "for") | (_, "select") => blocks.push("done"),
+                        (_, "while") | (_, "until") => {
+                            blocks.push("done");
+                            command_position = true;
+                        }
+                        (_, "function") => {
+                            // The body follows the name
+                            self.skip_blanks(false)?;
+                            self.skip_word()?;
+                            command_position = true;
+                        }
+                        (_, "then") | (_, "do") | (_, "else") | (_, "elif") | (_, "!") => {
+                            command_position = true
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        if let Some(close) = blocks.last() {
+            self.pos = start;
+            return Err(self.error(&format!("Missing {} of the command", close)));
+        }
+        Ok(())
+    }
+
+    /// Reads the delimiter of a here-document after `<<`.
+    fn heredoc(&mut self) -> Result<(), anyhow::Error> {
+        let strip_tabs = self.peek() == Some(b'-');
+        if strip_tabs {
+            self.pos += 1;
+        }
+        self.skip_blanks(false)?;
+        let start = self.pos;
+        self.skip_word()?;
+        if self.pos == start {
+            return Err(self.error("Missing here-document delimiter"));
+        }
+        let delimiter = unquote(&self.text[start..self.pos]);
+        self.heredocs.push((delimiter, strip_tabs));
+        Ok(())
+    }
+}
+
+/// Returns true for `name() ...` and `function name ...`.
+fn is_function_definition(command: &str) -> bool {
+    if let Some(rest) = command.strip_prefix("function") {
+        return rest.starts_with(&[' ', '\t'][..]);
+    }
+    let name_len = command
+        .find(|c: char| !(c == '_' || c == '-' || c.is_ascii_alphanumeric()))
+        .unwrap_or(command.len());
+    name_len > 0
+        && command[name_len..]
+            .trim_start_matches(&[' ', '\t'][..])
+            .starts_with("()")
+}
+
+fn utf8_len(first_byte: u8) -> usize {
+    match first_byte {
+        0xf0..=0xff => 4,
+        0xe0..=0xef => 3,
+        0xc0..=0xdf => 2,
+        _ => 1,
+    }
+}
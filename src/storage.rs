@@ -0,0 +1,144 @@
+/// Where `update-pages` reads repository databases from and writes pages to.
+#[async_trait::async_trait]
+pub trait Storage: Send + Sync {
+    /// Returns `None` when `key` doesn't exist.
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, anyhow::Error>;
+    /// Returns the hex-encoded MD5 digest of the stored object, or `None` when `key` doesn't exist.
+    async fn md5(&self, key: &str) -> Result<Option<String>, anyhow::Error>;
+    async fn put(&self, key: &str, content_type: &str, body: Vec<u8>) -> Result<(), anyhow::Error>;
+}
+
+pub struct S3Storage {
+    client: rusoto_s3::S3Client,
+    bucket: String,
+}
+
+impl S3Storage {
+    pub fn new(config: &crate::guzuta::S3Config) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            client: config.client()?,
+            bucket: config.bucket.clone(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Storage for S3Storage {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, anyhow::Error> {
+        use futures::TryStreamExt as _;
+        use rusoto_s3::S3 as _;
+
+        let resp = match self
+            .client
+            .get_object(rusoto_s3::GetObjectRequest {
+                bucket: self.bucket.clone(),
+                key: key.to_owned(),
+                ..Default::default()
+            })
+            .await
+        {
+            Ok(resp) => resp,
+            Err(rusoto_core::RusotoError::Service(rusoto_s3::GetObjectError::NoSuchKey(_))) => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let body = resp
+            .body
+            .unwrap()
+            .map_ok(|b| bytes::BytesMut::from(&b[..]))
+            .try_concat()
+            .await?;
+        Ok(Some(body.to_vec()))
+    }
+
+    async fn md5(&self, key: &str) -> Result<Option<String>, anyhow::Error> {
+        use rusoto_s3::S3 as _;
+
+        match self
+            .client
+            .head_object(rusoto_s3::HeadObjectRequest {
+                bucket: self.bucket.clone(),
+                key: key.to_owned(),
+                ..Default::default()
+            })
+            .await
+        {
+            // ETag is the MD5 digest unless the object was uploaded in multiple parts
+            Ok(resp) => Ok(resp.e_tag.map(|e_tag| e_tag.trim_matches('"').to_owned())),
+            Err(rusoto_core::RusotoError::Service(rusoto_s3::HeadObjectError::NoSuchKey(_))) => {
+                Ok(None)
+            }
+            Err(rusoto_core::RusotoError::Unknown(ref resp)) if resp.status.as_u16() == 404 => {
+                Ok(None)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn put(&self, key: &str, content_type: &str, body: Vec<u8>) -> Result<(), anyhow::Error> {
+        use md5::Digest as _;
+        use rusoto_s3::S3 as _;
+
+        let content_md5 = Some(base64::encode(md5::Md5::digest(&body)));
+        self.client
+            .put_object(rusoto_s3::PutObjectRequest {
+                bucket: self.bucket.clone(),
+                key: key.to_owned(),
+                content_type: Some(content_type.to_owned()),
+                content_md5,
+                body: Some(body.into()),
+                ..Default::default()
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+/// Stores objects as files under `root`, laid out the same way as keys in the bucket.
+pub struct LocalStorage {
+    root: std::path::PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new<P>(root: P) -> Self
+    where
+        P: Into<std::path::PathBuf>,
+    {
+        Self { root: root.into() }
+    }
+}
+
+#[async_trait::async_trait]
+impl Storage for LocalStorage {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, anyhow::Error> {
+        match tokio::fs::read(self.root.join(key)).await {
+            Ok(body) => Ok(Some(body)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn md5(&self, key: &str) -> Result<Option<String>, anyhow::Error> {
+        use md5::Digest as _;
+
+        Ok(self
+            .get(key)
+            .await?
+            .map(|body| format!("{:x}", md5::Md5::digest(&body))))
+    }
+
+    async fn put(
+        &self,
+        key: &str,
+        _content_type: &str,
+        body: Vec<u8>,
+    ) -> Result<(), anyhow::Error> {
+        let path = self.root.join(key);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(path, body).await?;
+        Ok(())
+    }
+}
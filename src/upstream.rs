@@ -0,0 +1,1133 @@
+/// Contents of `upstream.yml` in a package directory, which tells `update-upstream` how to
+/// follow a GitHub repository.
+#[derive(Debug, serde::Deserialize)]
+pub struct Config {
+    pub owner: String,
+    pub repo: String,
+    pub strategy: Strategy,
+    /// Handlebars template of pkgver, rendered with the variables of the upstream revision.
+    pub version: String,
+    /// Handlebars template of the tarball name saved into srcdest.
+    pub source: String,
+    /// Top-level PKGBUILD variables to rewrite, mapped to Handlebars templates of their values.
+    #[serde(default)]
+    pub variables: std::collections::BTreeMap<String, String>,
+    /// C headers in the tarball, relative to its top directory, whose `#define`s are provided as
+    /// variables after downloading. Missing headers are ignored as long as one of them exists.
+    #[serde(default)]
+    pub defines: Vec<String>,
+    /// Checks the GPG signature of the annotated tag before downloading. Only for `latest-tag`.
+    pub verify_tag: Option<VerifyTag>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct VerifyTag {
+    /// Keyring for gpgv(1), relative to the package directory.
+    pub keyring: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Strategy {
+    /// The head commit of the default branch, or the newest one among its latest commits which
+    /// passed all of `required_checks` (names of check runs or contexts of commit statuses).
+    /// Provides `sha`, `short_sha` and `commit_date` variables.
+    HeadCommit {
+        #[serde(default)]
+        required_checks: Vec<String>,
+    },
+    /// The greatest version among the tags matching `pattern`. Capture groups of `pattern` are
+    /// parsed as dot-separated numbers and compared in order.
+    /// Provides `tag`, `sha` and named capture groups of `pattern` as variables, and `tag_sha` of
+    /// the tag object for annotated tags.
+    LatestTag { pattern: String },
+    /// The tag of the latest release.
+    /// Provides `tag` and named capture groups of `pattern` as variables.
+    LatestRelease { pattern: Option<String> },
+}
+
+/// A resolved upstream revision.
+#[derive(Debug)]
+pub struct Revision {
+    /// Git ref to download the source tarball of.
+    pub git_ref: String,
+    pub variables: std::collections::BTreeMap<String, String>,
+}
+
+#[derive(Debug)]
+pub struct Package {
+    pub name: String,
+    pub dir: std::path::PathBuf,
+    pub repository: crate::guzuta::Repository,
+    pub config: Config,
+}
+
+impl Package {
+    /// Loads `upstream.yml` from a package directory in a guzuta repository, such as
+    /// `ruby-trunk/PKGBUILDs/ruby-trunk`.
+    pub fn load<P>(dir: P) -> Result<Self, anyhow::Error>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        use anyhow::Context as _;
+
+        let dir = dir.as_ref();
+        let path = dir.join("upstream.yml");
+        let file = std::fs::File::open(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        let config = serde_yaml::from_reader(file)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        let name = dir
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| anyhow::anyhow!("Invalid package directory {}", dir.display()))?
+            .to_owned();
+        let repo_dir = dir
+            .parent()
+            .and_then(|pkgbuild_dir| pkgbuild_dir.parent())
+            .ok_or_else(|| anyhow::anyhow!("Invalid package directory {}", dir.display()))?;
+        let repository = crate::guzuta::Repository::load(repo_dir)?;
+        Ok(Self {
+            name,
+            dir: dir.to_owned(),
+            repository,
+            config,
+        })
+    }
+
+    /// Finds every package having `upstream.yml` in the guzuta repositories under `root`.
+    pub fn discover<P>(root: P) -> Result<Vec<Self>, anyhow::Error>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        let mut dirs = Vec::new();
+        for repo in crate::guzuta::Repository::discover(root)? {
+            for entry in std::fs::read_dir(repo.pkgbuild_dir())? {
+                let path = entry?.path();
+                if path.join("upstream.yml").is_file() {
+                    dirs.push(path);
+                }
+            }
+        }
+        dirs.sort();
+        dirs.into_iter().map(Self::load).collect()
+    }
+
+    /// Downloads the source tarball of the latest upstream revision and rewrites the variables,
+    /// pkgrel and the checksum of the first source in PKGBUILD for it.
+    /// pkgrel is reset to 1 for a new pkgver and bumped for a new source with the same pkgver.
+    /// Nothing is downloaded when the variables in PKGBUILD already point to the upstream revision.
+    pub async fn update(
+        &self,
+        client: &crate::github::Client,
+    ) -> Result<UpdateStatus, anyhow::Error> {
+        let revision = self.config.strategy.resolve(client, &self.config).await?;
+        self.update_to(client, revision).await
+    }
+
+    /// Updates PKGBUILD like `update`, to the given commit instead of the latest one.
+    /// Only for `head-commit` packages.
+    pub async fn update_to_commit(
+        &self,
+        client: &crate::github::Client,
+        sha: &str,
+    ) -> Result<UpdateStatus, anyhow::Error> {
+        self.check_follows_commits(sha)?;
+        let commit: RepositoryCommit = client
+            .get(&format!(
+                "/repos/{}/{}/commits/{}",
+                self.config.owner, self.config.repo, sha
+            ))
+            .await?;
+        self.update_to(
+            client,
+            commit_revision(commit.sha, commit.commit.committer.date),
+        )
+        .await
+    }
+
+    /// Updates PKGBUILD like `update_to_commit`, to a commit of a local clone of the upstream
+    /// repository. Its committer date is read from the clone instead of the API.
+    pub async fn update_to_local_commit(
+        &self,
+        client: &crate::github::Client,
+        commit: &git2::Commit<'_>,
+    ) -> Result<UpdateStatus, anyhow::Error> {
+        let sha = commit.id().to_string();
+        self.check_follows_commits(&sha)?;
+        let date = chrono::DateTime::<chrono::Utc>::from_utc(
+            chrono::NaiveDateTime::from_timestamp(commit.committer().when().seconds(), 0),
+            chrono::Utc,
+        );
+        self.update_to(client, commit_revision(sha, date)).await
+    }
+
+    fn check_follows_commits(&self, sha: &str) -> Result<(), anyhow::Error> {
+        if let Strategy::HeadCommit { .. } = self.config.strategy {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!(
+                "{} doesn't follow commits and cannot be pinned to {}",
+                self.name,
+                sha
+            ))
+        }
+    }
+
+    async fn update_to(
+        &self,
+        client: &crate::github::Client,
+        mut revision: Revision,
+    ) -> Result<UpdateStatus, anyhow::Error> {
+        let pkgbuild_path = self.dir.join("PKGBUILD");
+        let mut pkgbuild: crate::pkgbuild::Pkgbuild =
+            tokio::fs::read_to_string(&pkgbuild_path).await?.parse()?;
+        let checksums = Checksums::find(&pkgbuild)?;
+        let old_pkgbuild = pkgbuild.clone();
+
+        let mut handlebars = handlebars::Handlebars::new();
+        handlebars.set_strict_mode(true);
+        let pkgver = handlebars.render_template(&self.config.version, &revision.variables)?;
+        revision.variables.insert("pkgver".to_owned(), pkgver);
+        let source = handlebars.render_template(&self.config.source, &revision.variables)?;
+        // Variables using defines can't be rendered until the tarball is downloaded, so only the
+        // others are compared here
+        let mut rendered = Vec::new();
+        for (name, template) in &self.config.variables {
+            match handlebars.render_template(template, &revision.variables) {
+                Ok(value) => rendered.push((name, value)),
+                Err(ref e) if !self.config.defines.is_empty() && is_missing_variable(e) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        if !rendered.is_empty()
+            && rendered
+                .iter()
+                .all(|(name, value)| pkgbuild.get(name).as_ref() == Some(value))
+        {
+            return Ok(UpdateStatus::UpToDate);
+        }
+
+        if let Some(ref verify_tag) = self.config.verify_tag {
+            self.verify_tag(client, &revision, verify_tag).await?;
+            self.check_tag_ref(client, &revision).await?;
+        }
+
+        let srcdest = self.repository.srcdest();
+        let path = srcdest.join(&source);
+        let mut known_checksums = KnownChecksums::load(&srcdest)?;
+        let checksum = match reuse(&path, known_checksums.get(&source), checksums).await? {
+            Some(checksum) => {
+                println!("Reuse {}", path.display());
+                checksum
+            }
+            None => {
+                let resp = client
+                    .archive(&self.config.owner, &self.config.repo, &revision.git_ref)
+                    .await?;
+                tokio::fs::create_dir_all(&srcdest).await?;
+                let (sha256, checksum) = download(resp, &path, checksums).await?;
+                if self.config.verify_tag.is_some() {
+                    // The tag could be moved while downloading the archive by its name
+                    if let Err(e) = self.check_tag_ref(client, &revision).await {
+                        let _ = std::fs::remove_file(&path);
+                        return Err(e);
+                    }
+                }
+                known_checksums.insert(source, sha256);
+                known_checksums.save()?;
+                checksum
+            }
+        };
+
+        if !self.config.defines.is_empty() {
+            for (name, value) in read_defines(&path, &self.config.defines)? {
+                revision.variables.entry(name).or_insert(value);
+            }
+        }
+        for (name, template) in &self.config.variables {
+            pkgbuild.set(
+                name,
+                &handlebars.render_template(template, &revision.variables)?,
+            )?;
+        }
+        pkgbuild.set_element(checksums.name(), 0, &checksum)?;
+        let pkgrel = next_pkgrel(&old_pkgbuild, &pkgbuild)?;
+        pkgbuild.set("pkgrel", &pkgrel)?;
+
+        crate::write_atomically(&pkgbuild_path, pkgbuild.to_string().as_bytes())?;
+        Ok(UpdateStatus::Updated)
+    }
+
+    /// Verifies the signature of the annotated tag with gpgv(1) against the committed keyring.
+    /// The signed payload must name the tag and, together with the signature, hash to `tag_sha`,
+    /// so that a signature of another tag can't be passed off as this one.
+    async fn verify_tag(
+        &self,
+        client: &crate::github::Client,
+        revision: &Revision,
+        verify_tag: &VerifyTag,
+    ) -> Result<(), anyhow::Error> {
+        use anyhow::Context as _;
+        use sha1::Digest as _;
+        use std::io::Write as _;
+        use std::os::unix::fs::DirBuilderExt as _;
+
+        let tag_sha = revision.variables.get("tag_sha").ok_or_else(|| {
+            anyhow::anyhow!(
+                "{} is not an annotated tag and cannot be verified",
+                revision.git_ref
+            )
+        })?;
+        let tag: Tag = client
+            .get(&format!(
+                "/repos/{}/{}/git/tags/{}",
+                self.config.owner, self.config.repo, tag_sha
+            ))
+            .await?;
+        let (signature, payload) = match (tag.verification.signature, tag.verification.payload) {
+            (Some(signature), Some(payload)) => (signature, payload),
+            _ => return Err(anyhow::anyhow!("Tag {} is not signed", revision.git_ref)),
+        };
+        let name = payload
+            .lines()
+            .take_while(|line| !line.is_empty())
+            .find_map(|line| line.strip_prefix("tag "));
+        if name != Some(revision.git_ref.as_str()) {
+            return Err(anyhow::anyhow!(
+                "Tag {} is signed as {}",
+                revision.git_ref,
+                name.unwrap_or("no tag")
+            ));
+        }
+        let mut hasher = sha1::Sha1::default();
+        hasher.update(format!("tag {}\0", payload.len() + signature.len()).as_bytes());
+        hasher.update(payload.as_bytes());
+        hasher.update(signature.as_bytes());
+        let sha = hex(&hasher.finalize());
+        if sha != *tag_sha {
+            return Err(anyhow::anyhow!(
+                "Tag {} is {} but its payload and signature hash to {}",
+                revision.git_ref,
+                tag_sha,
+                sha
+            ));
+        }
+
+        // gpgv looks for a keyring without slashes in ~/.gnupg
+        let keyring = std::fs::canonicalize(self.dir.join(&verify_tag.keyring))?;
+        // The directory is private and created exclusively, so that nobody else can replace the
+        // signature before gpgv reads it
+        let temp_dir =
+            std::env::temp_dir().join(format!("arch-wanko-cc-{}-{}", std::process::id(), tag_sha));
+        std::fs::DirBuilder::new()
+            .mode(0o700)
+            .create(&temp_dir)
+            .with_context(|| format!("Failed to create {}", temp_dir.display()))?;
+        let signature_path = temp_dir.join("tag.sig");
+        let result = (|| {
+            std::fs::write(&signature_path, signature)?;
+            let mut child = std::process::Command::new("gpgv")
+                .arg("--keyring")
+                .arg(&keyring)
+                .arg(&signature_path)
+                .arg("-")
+                .stdin(std::process::Stdio::piped())
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::piped())
+                .spawn()?;
+            child.stdin.take().unwrap().write_all(payload.as_bytes())?;
+            child.wait_with_output()
+        })();
+        let _ = std::fs::remove_dir_all(&temp_dir);
+        let output = result?;
+        if output.status.success() {
+            println!("Verified tag {} ({})", revision.git_ref, tag_sha);
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!(
+                "Failed to verify tag {} with {}: {}",
+                revision.git_ref,
+                keyring.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))
+        }
+    }
+
+    /// Fails unless the tag still points at the tag object verified by `verify_tag`, because the
+    /// archive is downloaded by the tag name.
+    async fn check_tag_ref(
+        &self,
+        client: &crate::github::Client,
+        revision: &Revision,
+    ) -> Result<(), anyhow::Error> {
+        let tag_sha = &revision.variables["tag_sha"];
+        let r: GitRef = client
+            .get(&format!(
+                "/repos/{}/{}/git/ref/tags/{}",
+                self.config.owner, self.config.repo, revision.git_ref
+            ))
+            .await?;
+        if r.object.sha == *tag_sha {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!(
+                "Tag {} was moved from the verified {} to {}",
+                revision.git_ref,
+                tag_sha,
+                r.object.sha
+            ))
+        }
+    }
+}
+
+/// Returns true when rendering failed only because a variable isn't provided, which may be one of
+/// the defines read after downloading. It's rendered again then, so typos are still reported.
+fn is_missing_variable(e: &handlebars::TemplateRenderError) -> bool {
+    e.as_render_error().is_some_and(|e| {
+        e.desc.starts_with("Variable ") && e.desc.ends_with(" not found in strict mode.")
+    })
+}
+
+/// Saves the response body to `path` and returns its SHA-256 and the checksum for PKGBUILD.
+/// The body goes to a temporary file first, which is renamed to `path` only after its size is
+/// checked against Content-Length, so that an interrupted download never leaves a truncated
+/// source behind.
+async fn download(
+    resp: reqwest::Response,
+    path: &std::path::Path,
+    checksums: Checksums,
+) -> Result<(String, String), anyhow::Error> {
+    use anyhow::Context as _;
+    use futures::StreamExt as _;
+    use tokio::io::AsyncWriteExt as _;
+
+    let temp_path = crate::temporary_path(path);
+    let result = async {
+        let content_length = resp.content_length();
+        let file = tokio::fs::File::create(&temp_path).await?;
+        let mut writer = tokio::io::BufWriter::new(file);
+        let mut stream = resp.bytes_stream();
+        let mut sha256 = sha2::Sha256::default();
+        let mut digest = checksums.digest();
+        let mut size = 0;
+        while let Some(item) = stream.next().await {
+            let b = item.with_context(|| format!("Failed to download {}", path.display()))?;
+            writer.write_all(&b).await?;
+            sha2::Digest::update(&mut sha256, &b);
+            digest.update(&b);
+            size += b.len() as u64;
+        }
+        writer.flush().await?;
+        writer.into_inner().sync_all().await?;
+        if let Some(content_length) = content_length {
+            if size != content_length {
+                return Err(anyhow::anyhow!(
+                    "Downloaded {} bytes for {} but Content-Length is {}",
+                    size,
+                    path.display(),
+                    content_length
+                ));
+            }
+        }
+        tokio::fs::rename(&temp_path, path).await?;
+        Ok((
+            hex(&sha2::Digest::finalize(sha256)),
+            hex(&digest.finalize()),
+        ))
+    }
+    .await;
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&temp_path).await;
+    }
+    result
+}
+
+/// Collects object-like macros defined in `headers` in the tarball at `path`.
+/// A macro defined as another macro is resolved to its value, and string literals are unquoted.
+fn read_defines(
+    path: &std::path::Path,
+    headers: &[String],
+) -> Result<std::collections::BTreeMap<String, String>, anyhow::Error> {
+    use std::io::Read as _;
+
+    let mut contents = std::collections::BTreeMap::new();
+    let file = std::fs::File::open(path)?;
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(file));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        // Strip the top directory such as ruby-<sha>/
+        let relative: std::path::PathBuf = entry.path()?.components().skip(1).collect();
+        if let Some(i) = headers
+            .iter()
+            .position(|header| std::path::Path::new(header) == relative)
+        {
+            let mut content = String::new();
+            entry.read_to_string(&mut content)?;
+            contents.insert(i, content);
+        }
+    }
+    if contents.is_empty() {
+        return Err(anyhow::anyhow!(
+            "None of {} is found in {}",
+            headers.join(", "),
+            path.display()
+        ));
+    }
+
+    let re =
+        regex::Regex::new(r"(?m)^[ \t]*#[ \t]*define[ \t]+([A-Za-z_][A-Za-z0-9_]*)[ \t]+(.*)$")
+            .unwrap();
+    let mut defines = std::collections::BTreeMap::new();
+    for content in contents.values() {
+        for captures in re.captures_iter(content) {
+            let value = strip_comment(&captures[2]).trim();
+            if value.is_empty() {
+                continue;
+            }
+            let value = if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+                value[1..value.len() - 1].to_owned()
+            } else {
+                value.to_owned()
+            };
+            defines.entry(captures[1].to_owned()).or_insert(value);
+        }
+    }
+    let resolved = defines
+        .iter()
+        .map(|(name, value)| {
+            let mut value = value;
+            // Follow at most a few aliases to avoid looping on self-referencing macros
+            for _ in 0..8 {
+                match defines.get(value) {
+                    Some(v) => value = v,
+                    None => break,
+                }
+            }
+            (name.clone(), value.clone())
+        })
+        .collect();
+    Ok(resolved)
+}
+
+fn strip_comment(s: &str) -> &str {
+    let end = [s.find("/*"), s.find("//")]
+        .iter()
+        .flatten()
+        .min()
+        .copied()
+        .unwrap_or(s.len());
+    &s[..end]
+}
+
+/// Returns the checksum for PKGBUILD of the already downloaded source when its SHA-256 matches the
+/// known one.
+async fn reuse(
+    path: &std::path::Path,
+    sha256: Option<&str>,
+    checksums: Checksums,
+) -> Result<Option<String>, anyhow::Error> {
+    use tokio::io::AsyncReadExt as _;
+
+    let sha256 = match sha256 {
+        Some(sha256) => sha256,
+        None => return Ok(None),
+    };
+    let mut file = match tokio::fs::File::open(path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let mut actual = sha2::Sha256::default();
+    let mut digest = checksums.digest();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        sha2::Digest::update(&mut actual, &buf[..n]);
+        digest.update(&buf[..n]);
+    }
+    if hex(&sha2::Digest::finalize(actual)) == sha256 {
+        Ok(Some(hex(&digest.finalize())))
+    } else {
+        eprintln!(
+            "{} doesn't match the known SHA-256, downloading it again",
+            path.display()
+        );
+        Ok(None)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// SHA-256 of the sources downloaded by `update-upstream`, kept in `SHA256SUMS` under srcdest in
+/// the format of sha256sum(1).
+#[derive(Debug)]
+struct KnownChecksums {
+    path: std::path::PathBuf,
+    checksums: std::collections::BTreeMap<String, String>,
+}
+
+impl KnownChecksums {
+    const FILE_NAME: &'static str = "SHA256SUMS";
+
+    fn load(srcdest: &std::path::Path) -> Result<Self, anyhow::Error> {
+        let path = srcdest.join(Self::FILE_NAME);
+        let mut checksums = std::collections::BTreeMap::new();
+        match std::fs::read_to_string(&path) {
+            Ok(content) => {
+                for line in content.lines() {
+                    let mut fields = line.splitn(2, "  ");
+                    match (fields.next(), fields.next()) {
+                        (Some(sha256), Some(filename)) => {
+                            checksums.insert(filename.to_owned(), sha256.to_owned());
+                        }
+                        _ => {
+                            return Err(anyhow::anyhow!(
+                                "Invalid line in {}: {}",
+                                path.display(),
+                                line
+                            ))
+                        }
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(
+                    anyhow::Error::new(e).context(format!("Failed to open {}", path.display()))
+                )
+            }
+        }
+        Ok(Self { path, checksums })
+    }
+
+    fn get(&self, filename: &str) -> Option<&str> {
+        self.checksums.get(filename).map(|sha256| sha256.as_str())
+    }
+
+    fn insert(&mut self, filename: String, sha256: String) {
+        self.checksums.insert(filename, sha256);
+    }
+
+    fn save(&self) -> Result<(), anyhow::Error> {
+        let content: String = self
+            .checksums
+            .iter()
+            .map(|(filename, sha256)| format!("{}  {}\n", sha256, filename))
+            .collect();
+        crate::write_atomically(&self.path, content.as_bytes())
+    }
+}
+
+/// Next step of bisecting the commits reachable from a bad commit but not from any good commits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bisect {
+    /// `commit` should be tested next, with `remaining` candidates including the bad commit.
+    Step { commit: git2::Oid, remaining: usize },
+    /// No commits are left to test and this is the first bad commit.
+    Found(git2::Oid),
+}
+
+/// Chooses the commit which splits the candidates in half like git-bisect(1), that is the one whose
+/// number of ancestors among the candidates is the closest to half of them.
+/// Ancestors are counted in one pass from the oldest candidates, as bit sets merged from parents.
+/// It fails when there are no candidates, that is when the bad commit is reachable from a good one.
+pub fn bisect(
+    repo: &git2::Repository,
+    good: &[git2::Oid],
+    bad: git2::Oid,
+) -> Result<Bisect, anyhow::Error> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)?;
+    revwalk.push(bad)?;
+    for oid in good {
+        revwalk.hide(*oid)?;
+    }
+    let candidates = revwalk.collect::<Result<Vec<_>, _>>()?;
+    let n = candidates.len();
+    if n == 0 {
+        return Err(anyhow::anyhow!(
+            "No commits between good and bad: {} is reachable from the good commits",
+            bad
+        ));
+    }
+    if n == 1 {
+        return Ok(Bisect::Found(bad));
+    }
+
+    let indices: std::collections::HashMap<_, _> = candidates
+        .iter()
+        .enumerate()
+        .map(|(i, oid)| (*oid, i))
+        .collect();
+    let words = n.div_ceil(64);
+    let mut ancestors: Vec<Vec<u64>> = Vec::with_capacity(n);
+    let mut best = (n, bad);
+    for (i, oid) in candidates.iter().enumerate() {
+        let mut set = vec![0; words];
+        set[i / 64] |= 1 << (i % 64);
+        for parent in repo.find_commit(*oid)?.parent_ids() {
+            if let Some(&j) = indices.get(&parent) {
+                for (word, parent_word) in set.iter_mut().zip(&ancestors[j]) {
+                    *word |= parent_word;
+                }
+            }
+        }
+        let count = set
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum::<usize>();
+        let distance = (2 * count).max(n) - (2 * count).min(n);
+        // Prefer the newer one of equally good commits
+        if distance <= best.0 {
+            best = (distance, *oid);
+        }
+        ancestors.push(set);
+    }
+    Ok(Bisect::Step {
+        commit: best.1,
+        remaining: n,
+    })
+}
+
+/// Result of `prune`.
+#[derive(Debug, Default)]
+pub struct PruneReport {
+    /// Deleted files and their sizes.
+    pub removed: Vec<(std::path::PathBuf, u64)>,
+    /// Number and total size of the files left in srcdest.
+    pub kept: usize,
+    pub kept_size: u64,
+}
+
+/// Deletes the files in srcdest of the repository which no PKGBUILD in it refers to in `source`
+/// or `source_<arch>`. PKGBUILDs having top-level commands which could assign sources, or sources
+/// which can't be evaluated, are refused.
+pub fn prune(repository: &crate::guzuta::Repository) -> Result<PruneReport, anyhow::Error> {
+    let mut referenced = std::collections::HashSet::new();
+    for entry in std::fs::read_dir(repository.pkgbuild_dir())? {
+        let path = entry?.path().join("PKGBUILD");
+        if !path.is_file() {
+            continue;
+        }
+        let pkgbuild: crate::pkgbuild::Pkgbuild = std::fs::read_to_string(&path)?
+            .parse()
+            .map_err(|e: anyhow::Error| e.context(format!("Failed to parse {}", path.display())))?;
+        // Pruning with unknown names could delete sources still in use
+        if let Some(command) = pkgbuild.commands().next() {
+            return Err(anyhow::anyhow!(
+                "Cannot prune with {}, whose top-level command may assign sources: {}",
+                path.display(),
+                command.lines().next().unwrap_or("")
+            ));
+        }
+        let variables = pkgbuild.evaluate();
+        // Architecture-specific sources such as `source_x86_64` are downloaded too
+        for name in variables
+            .names()
+            .filter(|name| *name == "source" || name.starts_with("source_"))
+        {
+            let sources = variables
+                .get_array(name)
+                .map_err(|e| e.context(format!("Cannot expand {} in {}", name, path.display())))?;
+            for source in sources {
+                referenced.insert(source_filename(&source).to_owned());
+            }
+        }
+    }
+
+    let srcdest = repository.srcdest();
+    let mut known_checksums = KnownChecksums::load(&srcdest)?;
+    let mut report = PruneReport::default();
+    let entries = match std::fs::read_dir(&srcdest) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(report),
+        Err(e) => return Err(e.into()),
+    };
+    let mut paths = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            paths.push((entry.path(), entry.metadata()?.len()));
+        }
+    }
+    paths.sort();
+    for (path, size) in paths {
+        let filename = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("")
+            .to_owned();
+        if filename == KnownChecksums::FILE_NAME || referenced.contains(&filename) {
+            report.kept += 1;
+            report.kept_size += size;
+        } else {
+            std::fs::remove_file(&path)?;
+            known_checksums.checksums.remove(&filename);
+            report.removed.push((path, size));
+        }
+    }
+    if !report.removed.is_empty() {
+        known_checksums.save()?;
+    }
+    Ok(report)
+}
+
+/// Returns the name which makepkg saves `source` as.
+fn source_filename(source: &str) -> &str {
+    match source.find("::") {
+        Some(i) => &source[..i],
+        None => source
+            .trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .unwrap_or(source),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateStatus {
+    Updated,
+    /// PKGBUILD already follows the upstream revision.
+    UpToDate,
+}
+
+/// Resets pkgrel to 1 when pkgver changes, and bumps it when pkgver stays the same but other
+/// variables or checksums change so that pacman still sees a newer version.
+fn next_pkgrel(
+    old: &crate::pkgbuild::Pkgbuild,
+    new: &crate::pkgbuild::Pkgbuild,
+) -> Result<String, anyhow::Error> {
+    let old_pkgrel = old
+        .get("pkgrel")
+        .ok_or_else(|| anyhow::anyhow!("pkgrel is not assigned in PKGBUILD"))?;
+    if old.evaluate().get("pkgver")? != new.evaluate().get("pkgver")? {
+        Ok("1".to_owned())
+    } else if old.to_string() == new.to_string() {
+        Ok(old_pkgrel)
+    } else {
+        let major = old_pkgrel.split('.').next().unwrap_or("");
+        let major: u32 = major
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid pkgrel {}: {}", old_pkgrel, e))?;
+        Ok((major + 1).to_string())
+    }
+}
+
+/// Checksum arrays which can be updated, in the order of preference.
+#[derive(Debug, Clone, Copy)]
+enum Checksums {
+    Sha512,
+    Sha256,
+    Sha1,
+    Md5,
+}
+
+impl Checksums {
+    fn find(pkgbuild: &crate::pkgbuild::Pkgbuild) -> Result<Self, anyhow::Error> {
+        [Self::Sha512, Self::Sha256, Self::Sha1, Self::Md5]
+            .iter()
+            .copied()
+            .find(|checksums| pkgbuild.get_array(checksums.name()).is_some())
+            .ok_or_else(|| anyhow::anyhow!("No checksum array is found in PKGBUILD"))
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Sha512 => "sha512sums",
+            Self::Sha256 => "sha256sums",
+            Self::Sha1 => "sha1sums",
+            Self::Md5 => "md5sums",
+        }
+    }
+
+    fn digest(self) -> Box<dyn sha2::digest::DynDigest + Send> {
+        match self {
+            Self::Sha512 => Box::new(sha2::Sha512::default()),
+            Self::Sha256 => Box::new(sha2::Sha256::default()),
+            Self::Sha1 => Box::new(sha1::Sha1::default()),
+            Self::Md5 => Box::new(md5::Md5::default()),
+        }
+    }
+}
+
+impl Strategy {
+    pub async fn resolve(
+        &self,
+        client: &crate::github::Client,
+        config: &Config,
+    ) -> Result<Revision, anyhow::Error> {
+        match *self {
+            Self::HeadCommit {
+                ref required_checks,
+            } => {
+                let commits: Vec<RepositoryCommit> = client
+                    .get(&format!("/repos/{}/{}/commits", config.owner, config.repo))
+                    .await?;
+                if required_checks.is_empty() {
+                    let head_commit = commits
+                        .into_iter()
+                        .next()
+                        .ok_or_else(|| anyhow::anyhow!("No commits found"))?;
+                    return Ok(commit_revision(
+                        head_commit.sha,
+                        head_commit.commit.committer.date,
+                    ));
+                }
+                let count = commits.len();
+                for commit in commits {
+                    if let Some(check) =
+                        failed_check(client, config, &commit.sha, required_checks).await?
+                    {
+                        eprintln!("Skip {}: {}", commit.sha, check);
+                        continue;
+                    }
+                    return Ok(commit_revision(commit.sha, commit.commit.committer.date));
+                }
+                Err(anyhow::anyhow!(
+                    "None of the latest {} commits passed {}",
+                    count,
+                    required_checks.join(", ")
+                ))
+            }
+            Self::LatestTag { ref pattern } => {
+                let pattern = regex::Regex::new(pattern)?;
+                let refs: Vec<GitRef> = client
+                    .get_all(&format!(
+                        "/repos/{}/{}/git/refs/tags",
+                        config.owner, config.repo
+                    ))
+                    .await?;
+                let mut latest: Option<(Vec<u64>, GitRef)> = None;
+                for r in refs {
+                    let tag = r.ref_.trim_start_matches("refs/tags/");
+                    let version = match parse_version(&pattern, tag) {
+                        Ok(version) => version,
+                        Err(e) => {
+                            eprintln!("Skip tag {}: {}", tag, e);
+                            continue;
+                        }
+                    };
+                    if latest.as_ref().is_none_or(|(v, _)| *v < version) {
+                        latest = Some((version, r));
+                    }
+                }
+                let (_, r) = latest.ok_or_else(|| anyhow::anyhow!("No tags found"))?;
+                let tag = r.ref_.trim_start_matches("refs/tags/").to_owned();
+                let mut variables = capture_variables(&pattern, &tag);
+                if r.object.type_ == "tag" {
+                    variables.insert("tag_sha".to_owned(), r.object.sha.clone());
+                }
+                variables.insert("sha".to_owned(), r.object.sha);
+                variables.insert("tag".to_owned(), tag.clone());
+                Ok(Revision {
+                    git_ref: tag,
+                    variables,
+                })
+            }
+            Self::LatestRelease { ref pattern } => {
+                let release: Release = client
+                    .get(&format!(
+                        "/repos/{}/{}/releases/latest",
+                        config.owner, config.repo
+                    ))
+                    .await?;
+                let mut variables = match pattern {
+                    Some(pattern) => {
+                        let pattern = regex::Regex::new(pattern)?;
+                        if !pattern.is_match(&release.tag_name) {
+                            return Err(anyhow::anyhow!(
+                                "Latest release {} doesn't match {}",
+                                release.tag_name,
+                                pattern
+                            ));
+                        }
+                        capture_variables(&pattern, &release.tag_name)
+                    }
+                    None => std::collections::BTreeMap::new(),
+                };
+                variables.insert("tag".to_owned(), release.tag_name.clone());
+                Ok(Revision {
+                    git_ref: release.tag_name,
+                    variables,
+                })
+            }
+        }
+    }
+}
+
+/// Parses a tag into a comparable version. Capture groups of `pattern` (or the whole match when
+/// it has none) are numbers separated by dots, and they are compared from left to right.
+fn parse_version(pattern: &regex::Regex, tag: &str) -> Result<Vec<u64>, anyhow::Error> {
+    let captures = pattern
+        .captures(tag)
+        .ok_or_else(|| anyhow::anyhow!("doesn't match {}", pattern))?;
+    let groups: Vec<_> = if captures.len() > 1 {
+        captures.iter().skip(1).flatten().collect()
+    } else {
+        captures.iter().flatten().collect()
+    };
+    let mut version = Vec::new();
+    for group in groups {
+        for component in group.as_str().split('.') {
+            version.push(component.parse().map_err(|e| {
+                anyhow::anyhow!("{:?} is not a numeric version component: {}", component, e)
+            })?);
+        }
+    }
+    Ok(version)
+}
+
+fn commit_revision(sha: String, date: chrono::DateTime<chrono::Utc>) -> Revision {
+    let mut variables = std::collections::BTreeMap::new();
+    variables.insert("short_sha".to_owned(), sha[0..10].to_owned());
+    variables.insert("commit_date".to_owned(), date.format("%Y%m%d").to_string());
+    variables.insert("sha".to_owned(), sha.clone());
+    Revision {
+        git_ref: sha,
+        variables,
+    }
+}
+
+/// Returns a description of the first required check which hasn't passed on the commit.
+/// A check passes when its latest check run succeeded, or otherwise its commit status is success.
+async fn failed_check(
+    client: &crate::github::Client,
+    config: &Config,
+    sha: &str,
+    required_checks: &[String],
+) -> Result<Option<String>, anyhow::Error> {
+    let mut combined_status: Option<CombinedStatus> = None;
+    for name in required_checks {
+        let check_runs: CheckRuns = client
+            .get_with_query(
+                &format!(
+                    "/repos/{}/{}/commits/{}/check-runs",
+                    config.owner, config.repo, sha
+                ),
+                &[("check_name", name.as_str()), ("filter", "latest")],
+            )
+            .await?;
+        if let Some(check_run) = check_runs.check_runs.into_iter().next() {
+            match (check_run.status.as_str(), check_run.conclusion.as_deref()) {
+                // A skipped check run didn't test the commit at all
+                ("completed", Some("success")) | ("completed", Some("neutral")) => continue,
+                (status, conclusion) => {
+                    return Ok(Some(format!(
+                        "{} is {}",
+                        name,
+                        conclusion.unwrap_or(status)
+                    )))
+                }
+            }
+        }
+
+        if combined_status.is_none() {
+            combined_status = Some(
+                client
+                    .get_with_query(
+                        &format!(
+                            "/repos/{}/{}/commits/{}/status",
+                            config.owner, config.repo, sha
+                        ),
+                        &[("per_page", "100")],
+                    )
+                    .await?,
+            );
+        }
+        let status = combined_status
+            .as_ref()
+            .unwrap()
+            .statuses
+            .iter()
+            .find(|status| status.context == *name);
+        match status {
+            Some(status) if status.state == "success" => {}
+            Some(status) => return Ok(Some(format!("{} is {}", name, status.state))),
+            None => return Ok(Some(format!("{} is not found", name))),
+        }
+    }
+    Ok(None)
+}
+
+fn capture_variables(
+    pattern: &regex::Regex,
+    s: &str,
+) -> std::collections::BTreeMap<String, String> {
+    let mut variables = std::collections::BTreeMap::new();
+    if let Some(captures) = pattern.captures(s) {
+        for name in pattern.capture_names().flatten() {
+            if let Some(m) = captures.name(name) {
+                variables.insert(name.to_owned(), m.as_str().to_owned());
+            }
+        }
+    }
+    variables
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct RepositoryCommit {
+    sha: String,
+    commit: Commit,
+}
+#[derive(Debug, serde::Deserialize)]
+struct Commit {
+    committer: CommitAuthor,
+}
+#[derive(Debug, serde::Deserialize)]
+struct CommitAuthor {
+    date: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct CheckRuns {
+    check_runs: Vec<CheckRun>,
+}
+#[derive(Debug, serde::Deserialize)]
+struct CheckRun {
+    status: String,
+    conclusion: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct CombinedStatus {
+    statuses: Vec<Status>,
+}
+#[derive(Debug, serde::Deserialize)]
+struct Status {
+    context: String,
+    state: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct GitRef {
+    #[serde(rename = "ref")]
+    ref_: String,
+    object: Object,
+}
+#[derive(Debug, serde::Deserialize)]
+struct Object {
+    sha: String,
+    #[serde(rename = "type")]
+    type_: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Tag {
+    verification: Verification,
+}
+#[derive(Debug, serde::Deserialize)]
+struct Verification {
+    signature: Option<String>,
+    payload: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Release {
+    tag_name: String,
+}
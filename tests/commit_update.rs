@@ -0,0 +1,192 @@
+//! Runs commit-update in a fresh git repository with staged PKGBUILD changes.
+
+mod common;
+
+fn setup(test: &str) -> std::path::PathBuf {
+    let root = common::temp_dir(test);
+    git(&root, &["init", "--quiet"]);
+    write(&root, "a/PKGBUILD", "pkgname=a\npkgver=1.0\npkgrel=1\n");
+    write(&root, "a/.SRCINFO", "pkgver = 1.0\n");
+    write(
+        &root,
+        "b/PKGBUILD",
+        "pkgname=b\nepoch=1\n_major=2\npkgver=${_major}.0\npkgrel=1\n",
+    );
+    git(&root, &["add", "--all"]);
+    git(&root, &["commit", "--quiet", "--message", "Initial commit"]);
+
+    write(&root, "a/PKGBUILD", "pkgname=a\npkgver=1.1\npkgrel=1\n");
+    write(&root, "a/.SRCINFO", "pkgver = 1.1\n");
+    write(
+        &root,
+        "b/PKGBUILD",
+        "pkgname=b\nepoch=1\n_major=2\npkgver=${_major}.1\npkgrel=1\n",
+    );
+    write(
+        &root,
+        "c/PKGBUILD",
+        "pkgname=c\npkgver=3.0\npkgrel=1\nbuild() { case \"$CARCH\" in x86_64) pkgver=9.9 ;; esac; pkgrel=5; }\n",
+    );
+    root
+}
+
+fn write(root: &std::path::Path, path: &str, content: &str) {
+    let path = root.join(path);
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, content).unwrap();
+}
+
+fn command(root: &std::path::Path, program: &str) -> std::process::Command {
+    let mut command = std::process::Command::new(program);
+    command
+        .current_dir(root)
+        .env("HOME", root)
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("GIT_AUTHOR_NAME", "test")
+        .env("GIT_AUTHOR_EMAIL", "test@example.com")
+        .env("GIT_COMMITTER_NAME", "test")
+        .env("GIT_COMMITTER_EMAIL", "test@example.com");
+    command
+}
+
+fn git(root: &std::path::Path, args: &[&str]) -> String {
+    let output = command(root, "git").args(args).output().unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn commit_update(root: &std::path::Path, args: &[&str]) -> std::process::Output {
+    command(root, env!("CARGO_BIN_EXE_commit-update"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn single_commit() {
+    let root = setup("single_commit");
+    git(&root, &["add", "--all"]);
+
+    let output = commit_update(&root, &[]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        git(&root, &["log", "--max-count=1", "--format=%B"]),
+        "Update 3 packages\n\nUpdate a 1.0-1 -> 1.1-1\nUpdate b 1:2.0-1 -> 1:2.1-1\nAdd c 3.0-1\n\n"
+    );
+    assert_eq!(git(&root, &["status", "--porcelain"]), "");
+}
+
+#[test]
+fn one_package() {
+    let root = setup("one_package");
+    git(&root, &["add", "b"]);
+
+    let output = commit_update(&root, &["--separate"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        git(&root, &["log", "--max-count=1", "--format=%B"]),
+        "Update b 1:2.0-1 -> 1:2.1-1\n\n"
+    );
+}
+
+#[test]
+fn separate_commits() {
+    let root = setup("separate_commits");
+    git(&root, &["add", "--all"]);
+
+    let output = commit_update(&root, &["--separate"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        git(&root, &["log", "--max-count=3", "--format=%s"]),
+        "Add c 3.0-1\nUpdate b 1:2.0-1 -> 1:2.1-1\nUpdate a 1.0-1 -> 1.1-1\n"
+    );
+    assert_eq!(
+        git(&root, &["show", "--format=", "--name-only", "HEAD~2"]),
+        "a/.SRCINFO\na/PKGBUILD\n"
+    );
+    assert_eq!(
+        git(&root, &["show", "--format=", "--name-only", "HEAD~1"]),
+        "b/PKGBUILD\n"
+    );
+    assert_eq!(
+        git(&root, &["show", "--format=", "--name-only", "HEAD"]),
+        "c/PKGBUILD\n"
+    );
+    assert_eq!(git(&root, &["status", "--porcelain"]), "");
+}
+
+#[test]
+fn separate_commits_with_unowned_file() {
+    let root = setup("separate_commits_with_unowned_file");
+    write(&root, "README.md", "packages\n");
+    git(&root, &["add", "--all"]);
+    let status = git(&root, &["status", "--porcelain"]);
+
+    let output = commit_update(&root, &["--separate"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Staged files not belonging to any package: README.md"));
+    assert_eq!(git(&root, &["status", "--porcelain"]), status);
+    assert_eq!(
+        git(&root, &["log", "--max-count=1", "--format=%s"]),
+        "Initial commit\n"
+    );
+}
+
+#[test]
+fn separate_commits_with_submodule() {
+    let root = setup("separate_commits_with_submodule");
+    let upstream = common::temp_dir("separate_commits_with_submodule-d");
+    git(&upstream, &["init", "--quiet"]);
+    write(&upstream, "PKGBUILD", "pkgname=d\npkgver=4.0\npkgrel=1\n");
+    git(&upstream, &["add", "--all"]);
+    git(
+        &upstream,
+        &["commit", "--quiet", "--message", "Initial commit"],
+    );
+    git(&root, &["add", "--all"]);
+    git(
+        &root,
+        &[
+            "-c",
+            "protocol.file.allow=always",
+            "submodule",
+            "add",
+            "--quiet",
+            upstream.to_str().unwrap(),
+            "d",
+        ],
+    );
+
+    let output = commit_update(&root, &["--separate"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        git(&root, &["log", "--max-count=4", "--format=%s"]),
+        "Add d 4.0-1\nAdd c 3.0-1\nUpdate b 1:2.0-1 -> 1:2.1-1\nUpdate a 1.0-1 -> 1.1-1\n"
+    );
+    assert_eq!(
+        git(&root, &["show", "--format=", "--name-only", "HEAD"]),
+        ".gitmodules\nd\n"
+    );
+    assert_eq!(git(&root, &["status", "--porcelain"]), "");
+}
@@ -0,0 +1,191 @@
+//! Helpers shared by the integration tests. Each test crate uses only some of them.
+#![allow(dead_code)]
+
+use std::io::{BufRead as _, Read as _, Write as _};
+
+/// Returns an empty directory named after `test` under `CARGO_TARGET_TMPDIR`.
+pub fn temp_dir(test: &str) -> std::path::PathBuf {
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join(test);
+    if path.exists() {
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+    std::fs::create_dir_all(&path).unwrap();
+    path
+}
+
+pub struct Response {
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(body: Vec<u8>) -> Self {
+        Self {
+            headers: vec![],
+            body,
+        }
+    }
+
+    pub fn json(value: &serde_json::Value) -> Self {
+        Self::new(value.to_string().into_bytes())
+            .header("Content-Type", "application/json".to_owned())
+    }
+
+    /// Serves `tests/fixtures/<name>` as JSON.
+    pub fn fixture(name: &str) -> Self {
+        Self::new(fixture(name)).header("Content-Type", "application/json".to_owned())
+    }
+
+    pub fn header(mut self, name: &str, value: String) -> Self {
+        self.headers.push((name.to_owned(), value));
+        self
+    }
+}
+
+/// Responses of `Server` by request path.
+#[derive(Default)]
+pub struct Routes(std::collections::HashMap<String, Response>);
+
+impl Routes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn route<S>(mut self, path: S, response: Response) -> Self
+    where
+        S: Into<String>,
+    {
+        self.0.insert(path.into(), response);
+        self
+    }
+
+    /// Serves `body` as the archive of `git_ref` at `/<owner>/<repo>/archive/<git_ref>.tar.gz`.
+    pub fn archive(self, owner: &str, repo: &str, git_ref: &str, body: &[u8]) -> Self {
+        self.route(
+            format!("/{}/{}/archive/{}.tar.gz", owner, repo, git_ref),
+            Response::new(body.to_vec()),
+        )
+    }
+}
+
+/// Minimal HTTP/1.1 server answering GET requests from a fixed table of paths.
+/// Requested paths are recorded so that tests can check what was downloaded.
+pub struct Server {
+    addr: std::net::SocketAddr,
+    requests: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+}
+
+impl Server {
+    /// Starts the server with the routes built by `routes`, which is given the server URL.
+    pub fn start<F>(routes: F) -> Self
+    where
+        F: FnOnce(&str) -> Routes,
+    {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let routes = std::sync::Arc::new(routes(&format!("http://{}", addr)));
+        let requests = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = stream.unwrap();
+                let routes = routes.clone();
+                let log = log.clone();
+                std::thread::spawn(move || handle(stream, &routes, &log));
+            }
+        });
+        Self { addr, requests }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+fn handle(stream: std::net::TcpStream, routes: &Routes, log: &std::sync::Mutex<Vec<String>>) {
+    let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).unwrap();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        if line == "\r\n" || line.is_empty() {
+            break;
+        }
+    }
+    let path = request_line.split(' ').nth(1).unwrap_or("").to_owned();
+    log.lock().unwrap().push(path.clone());
+
+    let mut stream = stream;
+    match routes.0.get(&path) {
+        Some(resp) => {
+            let mut head = "HTTP/1.1 200 OK\r\nConnection: close\r\n".to_owned();
+            if !resp
+                .headers
+                .iter()
+                .any(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+            {
+                head.push_str(&format!("Content-Length: {}\r\n", resp.body.len()));
+            }
+            for (name, value) in &resp.headers {
+                head.push_str(&format!("{}: {}\r\n", name, value));
+            }
+            head.push_str("\r\n");
+            stream.write_all(head.as_bytes()).unwrap();
+            stream.write_all(&resp.body).unwrap();
+        }
+        None => {
+            stream
+                .write_all(
+                    b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                )
+                .unwrap();
+        }
+    }
+    let _ = stream.flush();
+}
+
+pub fn fixture(name: &str) -> Vec<u8> {
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(name);
+    let mut body = Vec::new();
+    std::fs::File::open(path)
+        .unwrap()
+        .read_to_end(&mut body)
+        .unwrap();
+    body
+}
+
+/// Builds a gzipped tarball having `files` under `prefix/`, like GitHub archives.
+pub fn tarball(prefix: &str, files: &[(&str, &str)]) -> Vec<u8> {
+    let encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    let mut builder = tar::Builder::new(encoder);
+    for (path, content) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(
+                &mut header,
+                format!("{}/{}", prefix, path),
+                content.as_bytes(),
+            )
+            .unwrap();
+    }
+    builder.into_inner().unwrap().finish().unwrap()
+}
+
+pub fn sha256(body: &[u8]) -> String {
+    use sha2::Digest as _;
+    sha2::Sha256::digest(body)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
@@ -0,0 +1,110 @@
+//! Parses `desc` entries of repository databases.
+
+const DESC: &str = "%FILENAME%
+nkf-2.1.5-1-x86_64.pkg.tar.zst
+
+%NAME%
+nkf
+
+%VERSION%
+2.1.5-1
+
+%DESC%
+Network Kanji code conversion Filter
+
+%CSIZE%
+72483
+
+%ISIZE%
+185434
+
+%LICENSE%
+custom
+zlib
+
+%ARCH%
+x86_64
+
+%BUILDDATE%
+1605000000
+
+%DEPENDS%
+glibc
+perl>=5.30
+
+%OPTDEPENDS%
+python: for the Python binding
+
+%XDATA%
+pkgtype=pkg
+
+";
+
+#[test]
+fn parse() {
+    let desc: arch_wanko_cc::desc::Desc = DESC.parse().unwrap();
+    assert_eq!(desc.filename, "nkf-2.1.5-1-x86_64.pkg.tar.zst");
+    assert_eq!(desc.name, "nkf");
+    assert_eq!(desc.base, None);
+    assert_eq!(desc.version, "2.1.5-1");
+    assert_eq!(
+        desc.desc.as_deref(),
+        Some("Network Kanji code conversion Filter")
+    );
+    assert_eq!(desc.csize, Some(72483));
+    assert_eq!(desc.isize, Some(185434));
+    assert_eq!(desc.builddate, 1605000000);
+}
+
+#[test]
+fn list_fields() {
+    let desc: arch_wanko_cc::desc::Desc = DESC.parse().unwrap();
+    assert_eq!(desc.license, vec!["custom", "zlib"]);
+    assert_eq!(desc.depends, vec!["glibc", "perl>=5.30"]);
+    assert_eq!(desc.optdepends, vec!["python: for the Python binding"]);
+    assert!(desc.groups.is_empty());
+    assert!(desc.makedepends.is_empty());
+}
+
+#[test]
+fn unknown_fields() {
+    let desc: arch_wanko_cc::desc::Desc = DESC.parse().unwrap();
+    assert_eq!(desc.extra.len(), 1);
+    assert_eq!(desc.extra["XDATA"], vec!["pkgtype=pkg"]);
+}
+
+#[test]
+fn duplicated_single_value() {
+    let body = DESC.replace("%NAME%\nnkf\n", "%NAME%\nnkf\nnkf-git\n");
+    let e = body.parse::<arch_wanko_cc::desc::Desc>().unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "Expected a single value for NAME in desc file, but got 2"
+    );
+}
+
+#[test]
+fn missing_field() {
+    let body = DESC.replace("%BUILDDATE%\n1605000000\n", "");
+    let e = body.parse::<arch_wanko_cc::desc::Desc>().unwrap_err();
+    assert_eq!(e.to_string(), "Failed to find BUILDDATE from desc file");
+}
+
+#[test]
+fn parse_files() {
+    assert_eq!(
+        arch_wanko_cc::desc::parse_files("%FILES%\nusr/\nusr/bin/\nusr/bin/nkf\n\n"),
+        vec!["usr/", "usr/bin/", "usr/bin/nkf"]
+    );
+    assert!(arch_wanko_cc::desc::parse_files("").is_empty());
+}
+
+#[test]
+fn format_size() {
+    assert_eq!(arch_wanko_cc::format_size(0), "0 B");
+    assert_eq!(arch_wanko_cc::format_size(1023), "1023 B");
+    assert_eq!(arch_wanko_cc::format_size(1024), "1.0 KiB");
+    assert_eq!(arch_wanko_cc::format_size(1536 * 1024), "1.5 MiB");
+    assert_eq!(arch_wanko_cc::format_size(3 << 30), "3.0 GiB");
+    assert_eq!(arch_wanko_cc::format_size(5000 << 40), "5000.0 TiB");
+}
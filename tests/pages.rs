@@ -0,0 +1,414 @@
+//! Tests the parts of update-pages which live in the library, with `LocalStorage` in place of S3
+//! and databases built in memory.
+
+mod common;
+
+fn desc(name: &str, version: &str, builddate: i64) -> arch_wanko_cc::desc::Desc {
+    format!(
+        "%FILENAME%\n{0}-{1}-x86_64.pkg.tar.zst\n\n%NAME%\n{0}\n\n%VERSION%\n{1}\n\n%BUILDDATE%\n{2}\n\n",
+        name, version, builddate
+    )
+    .parse()
+    .unwrap()
+}
+
+fn storage(test: &str) -> arch_wanko_cc::storage::LocalStorage {
+    arch_wanko_cc::storage::LocalStorage::new(common::temp_dir(test))
+}
+
+#[test]
+fn dependency_name() {
+    assert_eq!(arch_wanko_cc::pages::dependency_name("glibc"), "glibc");
+    assert_eq!(arch_wanko_cc::pages::dependency_name("perl>=5.30"), "perl");
+    assert_eq!(arch_wanko_cc::pages::dependency_name("foo<2"), "foo");
+    assert_eq!(arch_wanko_cc::pages::dependency_name("ruby=3.0.0"), "ruby");
+    assert_eq!(
+        arch_wanko_cc::pages::dependency_name("python: for the Python binding"),
+        "python"
+    );
+}
+
+#[test]
+fn feed_state_update() {
+    let old = desc("nkf", "2.1.4-1", 1600000000);
+    let new = desc("nkf", "2.1.5-1", 1605000000);
+    let other = desc("abc", "1.0-1", 1605000000);
+
+    let mut state = arch_wanko_cc::pages::FeedState::default();
+    state.update("aur-eagletmt", vec![("x86_64", &old)]);
+    state.update(
+        "aur-eagletmt",
+        vec![("x86_64", &new), ("x86_64", &other), ("i686", &new)],
+    );
+    // Builds already in the state aren't added twice
+    state.update("aur-eagletmt", vec![("x86_64", &new)]);
+
+    let entries: Vec<_> = state
+        .entries
+        .iter()
+        .map(|entry| {
+            (
+                entry.architecture.as_str(),
+                entry.name.as_str(),
+                entry.version.as_str(),
+            )
+        })
+        .collect();
+    // Newest first, then by name. The replaced build stays in the feed
+    assert_eq!(
+        entries,
+        vec![
+            ("x86_64", "abc", "1.0-1"),
+            ("x86_64", "nkf", "2.1.5-1"),
+            ("i686", "nkf", "2.1.5-1"),
+            ("x86_64", "nkf", "2.1.4-1"),
+        ]
+    );
+    assert!(state
+        .entries
+        .iter()
+        .all(|entry| entry.repository == "aur-eagletmt"));
+}
+
+#[test]
+fn feed_state_truncate() {
+    let descs: Vec<_> = (0..arch_wanko_cc::pages::FEED_ENTRIES as i64 + 10)
+        .map(|i| desc(&format!("pkg{}", i), "1.0-1", 1600000000 + i))
+        .collect();
+    let mut state = arch_wanko_cc::pages::FeedState::default();
+    state.update("repo", descs.iter().map(|desc| ("x86_64", desc)));
+    assert_eq!(state.entries.len(), arch_wanko_cc::pages::FEED_ENTRIES);
+    assert_eq!(
+        state.entries[0].name,
+        format!("pkg{}", arch_wanko_cc::pages::FEED_ENTRIES + 9)
+    );
+}
+
+#[tokio::test]
+async fn feed_state_fetch() {
+    use arch_wanko_cc::storage::Storage as _;
+
+    let storage = storage("feed_state_fetch");
+    let state = arch_wanko_cc::pages::FeedState::fetch(&storage, "repo")
+        .await
+        .unwrap();
+    assert!(state.entries.is_empty());
+
+    let mut state = arch_wanko_cc::pages::FeedState::default();
+    state.update(
+        "repo",
+        vec![("x86_64", &desc("nkf", "2.1.5-1", 1605000000))],
+    );
+    storage
+        .put(
+            "repo/feed-state.json",
+            "application/json",
+            serde_json::to_vec(&state).unwrap(),
+        )
+        .await
+        .unwrap();
+    let state = arch_wanko_cc::pages::FeedState::fetch(&storage, "repo")
+        .await
+        .unwrap();
+    assert_eq!(state.entries.len(), 1);
+    assert_eq!(state.entries[0].name, "nkf");
+}
+
+#[tokio::test]
+async fn upload_skips_unchanged() {
+    use arch_wanko_cc::pages::UploadStatus;
+    use arch_wanko_cc::storage::Storage as _;
+
+    let storage = storage("upload_skips_unchanged");
+    let upload = |body: &str| {
+        arch_wanko_cc::pages::upload(
+            &storage,
+            false,
+            "repo/index.html",
+            "text/html",
+            body.to_owned(),
+        )
+    };
+    assert_eq!(upload("<p>1</p>\n").await.unwrap(), UploadStatus::Uploaded);
+    assert_eq!(upload("<p>1</p>\n").await.unwrap(), UploadStatus::Skipped);
+    assert_eq!(upload("<p>2</p>\n").await.unwrap(), UploadStatus::Uploaded);
+    assert_eq!(
+        storage.get("repo/index.html").await.unwrap().unwrap(),
+        b"<p>2</p>\n"
+    );
+}
+
+#[tokio::test]
+async fn upload_dry_run() {
+    use arch_wanko_cc::pages::UploadStatus;
+    use arch_wanko_cc::storage::Storage as _;
+
+    let storage = storage("upload_dry_run");
+    let upload = |body: &str| {
+        arch_wanko_cc::pages::upload(&storage, true, "index.html", "text/html", body.to_owned())
+    };
+    assert_eq!(
+        upload("a\n").await.unwrap(),
+        UploadStatus::Changed("--- /dev/null\n+++ b/index.html\n@@ -0,0 +1 @@\n+a\n".to_owned())
+    );
+    assert_eq!(storage.get("index.html").await.unwrap(), None);
+
+    storage
+        .put("index.html", "text/html", b"a\n".to_vec())
+        .await
+        .unwrap();
+    assert_eq!(upload("a\n").await.unwrap(), UploadStatus::Skipped);
+    assert_eq!(
+        upload("b\n").await.unwrap(),
+        UploadStatus::Changed(
+            "--- a/index.html\n+++ b/index.html\n@@ -1 +1 @@\n-a\n+b\n".to_owned()
+        )
+    );
+    assert_eq!(storage.get("index.html").await.unwrap().unwrap(), b"a\n");
+}
+
+/// Builds a gzipped repository database from `(directory, filename, body)` entries.
+fn database(entries: &[(&str, &str, &str)]) -> Vec<u8> {
+    let encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    let mut builder = tar::Builder::new(encoder);
+    for (dir, filename, body) in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(body.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(
+                &mut header,
+                format!("{}/{}", dir, filename),
+                body.as_bytes(),
+            )
+            .unwrap();
+    }
+    builder.into_inner().unwrap().finish().unwrap()
+}
+
+fn desc_body(name: &str, version: &str, relations: &str) -> String {
+    format!(
+        "%FILENAME%\n{0}-{1}-x86_64.pkg.tar.zst\n\n%NAME%\n{0}\n\n%VERSION%\n{1}\n\n%BUILDDATE%\n1605000000\n\n{2}",
+        name, version, relations
+    )
+}
+
+/// `repo-a` has foo with its files, depending on packages in `repo-b`, which has no files
+/// database.
+fn repositories() -> Vec<arch_wanko_cc::pages::RepositoryInput> {
+    let foo_desc = desc_body(
+        "foo",
+        "1.0-1",
+        "%DEPENDS%\nbar>=1.0\nlibbaz.so=1-64\nmissing\n\n%OPTDEPENDS%\nqux: for the qux backend\n\n",
+    );
+    let foo_files = "%FILES%\nusr/\nusr/bin/\nusr/bin/foo\nusr/share/foo/a.txt\n\n";
+    let bar_desc = desc_body("bar", "1.0-1", "");
+    let baz_desc = desc_body("baz", "2.0-1", "%PROVIDES%\nlibbaz.so=1-64\n\n");
+    let a = arch_wanko_cc::pages::RepositoryInput {
+        name: "repo-a".to_owned(),
+        url: "https://a.example.com".to_owned(),
+        architectures: vec![
+            arch_wanko_cc::pages::ArchitectureInput::read(
+                "x86_64".to_owned(),
+                &database(&[("foo-1.0-1", "desc", &foo_desc)]),
+                Some(&database(&[("foo-1.0-1", "files", foo_files)])),
+            )
+            .unwrap(),
+            arch_wanko_cc::pages::ArchitectureInput::read(
+                "i686".to_owned(),
+                &database(&[("foo-1.0-1", "desc", &foo_desc)]),
+                Some(&database(&[])),
+            )
+            .unwrap(),
+        ],
+    };
+    let b = arch_wanko_cc::pages::RepositoryInput {
+        name: "repo-b".to_owned(),
+        url: "https://a.example.com".to_owned(),
+        architectures: vec![arch_wanko_cc::pages::ArchitectureInput::read(
+            "x86_64".to_owned(),
+            &database(&[
+                ("bar-1.0-1", "desc", &bar_desc),
+                ("baz-2.0-1", "desc", &baz_desc),
+            ]),
+            None,
+        )
+        .unwrap()],
+    };
+    vec![a, b]
+}
+
+#[test]
+fn architecture_input_read() {
+    let repositories = repositories();
+    let x86_64 = &repositories[0].architectures[0];
+    assert_eq!(x86_64.packages.len(), 1);
+    assert_eq!(x86_64.packages[0].desc.name, "foo");
+    assert_eq!(
+        x86_64.packages[0].builddate_str,
+        "2020-11-10T09:20:00+00:00"
+    );
+    assert_eq!(
+        x86_64.packages[0].files,
+        vec!["usr/", "usr/bin/", "usr/bin/foo", "usr/share/foo/a.txt"]
+    );
+    // The files database doesn't list foo
+    assert!(repositories[0].architectures[1].packages[0]
+        .files
+        .is_empty());
+
+    // No files database at all
+    let packages: Vec<_> = repositories[1].architectures[0]
+        .packages
+        .iter()
+        .map(|package| (package.desc.name.as_str(), package.files.len()))
+        .collect();
+    assert_eq!(packages, vec![("bar", 0), ("baz", 0)]);
+}
+
+#[test]
+fn package_pages() {
+    let repositories = repositories();
+    let links = arch_wanko_cc::pages::PackageLinks::new(&repositories);
+    assert_eq!(links.href("bar"), Some("/repo-b/packages/bar/".to_owned()));
+    // Provided names link to the provider
+    assert_eq!(
+        links.href("libbaz.so"),
+        Some("/repo-b/packages/baz/".to_owned())
+    );
+    assert_eq!(links.href("missing"), None);
+
+    let pages = repositories[0].package_pages(&links);
+    assert_eq!(pages.len(), 1);
+    assert_eq!(pages[0].repository, "repo-a");
+    assert_eq!(pages[0].name, "foo");
+    let architectures: Vec<_> = pages[0].builds.iter().map(|b| b.architecture).collect();
+    assert_eq!(architectures, vec!["x86_64", "i686"]);
+    let relations: Vec<_> = pages[0].builds[0]
+        .relations
+        .iter()
+        .map(|relation| {
+            (
+                relation.kind,
+                relation
+                    .entries
+                    .iter()
+                    .map(|entry| (entry.text.as_str(), entry.href.as_deref()))
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        relations,
+        vec![
+            (
+                "depends",
+                vec![
+                    ("bar>=1.0", Some("/repo-b/packages/bar/")),
+                    ("libbaz.so=1-64", Some("/repo-b/packages/baz/")),
+                    ("missing", None),
+                ]
+            ),
+            ("optdepends", vec![("qux: for the qux backend", None)]),
+        ]
+    );
+
+    let pages = repositories[1].package_pages(&links);
+    let names: Vec<_> = pages.iter().map(|page| page.name).collect();
+    assert_eq!(names, vec!["bar", "baz"]);
+    assert_eq!(pages[1].builds[0].relations[0].kind, "provides");
+}
+
+#[test]
+fn files_page() {
+    let repositories = repositories();
+    let page = repositories[0].files_page();
+    assert_eq!(page.repository, "repo-a");
+    let files: Vec<_> = page.architectures[0]
+        .files
+        .iter()
+        .map(|file| (file.path, file.package))
+        .collect();
+    // Directories are left out
+    assert_eq!(
+        files,
+        vec![("usr/bin/foo", "foo"), ("usr/share/foo/a.txt", "foo")]
+    );
+    assert!(page.architectures[1].files.is_empty());
+    assert!(repositories[1].files_page().architectures[0]
+        .files
+        .is_empty());
+}
+
+#[test]
+fn packages_json() {
+    let repositories = repositories();
+    let json =
+        serde_json::to_value(arch_wanko_cc::pages::PackagesJson::new(&repositories[0])).unwrap();
+    assert_eq!(
+        json["schema_version"],
+        arch_wanko_cc::pages::JSON_SCHEMA_VERSION
+    );
+    assert_eq!(json["repository"], "repo-a");
+    let package = &json["architectures"][0]["packages"][0];
+    assert_eq!(package["name"], "foo");
+    assert_eq!(package["version"], "1.0-1");
+    assert_eq!(
+        package["depends"],
+        serde_json::json!(["bar>=1.0", "libbaz.so=1-64", "missing"])
+    );
+    assert_eq!(
+        package["files"],
+        serde_json::json!(["usr/", "usr/bin/", "usr/bin/foo", "usr/share/foo/a.txt"])
+    );
+    // Only the desc fields and files are published, not the fields for the templates
+    assert!(package.get("builddate_str").is_none());
+
+    let json =
+        serde_json::to_value(arch_wanko_cc::pages::PackagesJson::new(&repositories[1])).unwrap();
+    assert_eq!(
+        json["architectures"][0]["packages"][1]["files"],
+        serde_json::json!([])
+    );
+}
+
+#[test]
+fn render_templates() {
+    let handlebars =
+        arch_wanko_cc::pages::templates(concat!(env!("CARGO_MANIFEST_DIR"), "/templates")).unwrap();
+    let repositories = repositories();
+    let links = arch_wanko_cc::pages::PackageLinks::new(&repositories);
+    for repository in &repositories {
+        let html = handlebars.render("repository", repository).unwrap();
+        assert!(
+            html.contains("Server = https://a.example.com/$repo/os/$arch</code>"),
+            "{}",
+            html
+        );
+        handlebars
+            .render("files", &repository.files_page())
+            .unwrap();
+        for page in repository.package_pages(&links) {
+            handlebars.render("package", &page).unwrap();
+        }
+    }
+    let html = handlebars
+        .render("package", &repositories[0].package_pages(&links)[0])
+        .unwrap();
+    assert!(
+        html.contains(r#"<a href="/repo-b/packages/baz/">libbaz.so&#x3D;1-64</a>"#),
+        "{}",
+        html
+    );
+    assert!(html.contains("<li>missing</li>"), "{}", html);
+    assert!(html.contains("/usr/bin/foo&#x000A;"), "{}", html);
+
+    // Fields missing from the input are errors in strict mode
+    assert!(handlebars
+        .render(
+            "package",
+            &serde_json::json!({"repository": "repo-a", "builds": []})
+        )
+        .is_err());
+}
@@ -0,0 +1,351 @@
+//! Evaluates PKGBUILDs without bash. Expected values are what bash gives by sourcing them.
+
+const PKGBUILD: &str = r#"_baseversion=8.2
+_patchlevel=1968
+pkgname=foo-bar
+_pkgname=${pkgname%-*}
+pkgver=${_baseversion/./}.${_patchlevel}
+_major=${pkgver%%.*}
+_tail=${pkgver#*.}
+_default=${_missing:-default}
+_upper=${pkgname^^}
+_replaced=${pkgname//o/0}
+_length=${#pkgname}
+epoch=1
+pkgrel=2
+_array=(a "b c" 'd$e')
+source=("${_array[@]}" "$_pkgname-$pkgver.tar.gz"
+        "${_array[1]}")
+_date=$(date +%Y%m%d)
+
+build() {
+  pkgver=0
+}
+"#;
+
+fn evaluate(text: &str) -> arch_wanko_cc::pkgbuild::Variables {
+    text.parse::<arch_wanko_cc::pkgbuild::Pkgbuild>()
+        .unwrap()
+        .evaluate()
+}
+
+#[test]
+fn parameter_expansions() {
+    let variables = evaluate(PKGBUILD);
+    let get = |name: &str| variables.get(name).unwrap();
+    assert_eq!(get("pkgver"), "82.1968");
+    assert_eq!(get("_pkgname"), "foo");
+    assert_eq!(get("_major"), "82");
+    assert_eq!(get("_tail"), "1968");
+    assert_eq!(get("_default"), "default");
+    assert_eq!(get("_upper"), "FOO-BAR");
+    assert_eq!(get("_replaced"), "f00-bar");
+    assert_eq!(get("_length"), "7");
+    assert_eq!(
+        variables.get_array("source").unwrap(),
+        vec!["a", "b c", "d$e", "foo-82.1968.tar.gz", "b c"]
+    );
+}
+
+#[test]
+fn full_version() {
+    assert_eq!(evaluate(PKGBUILD).full_version().unwrap(), "1:82.1968-2");
+    assert_eq!(
+        evaluate("pkgver=1.0\npkgrel=3\nepoch=0\n")
+            .full_version()
+            .unwrap(),
+        "1.0-3"
+    );
+}
+
+#[test]
+fn command_substitution() {
+    let variables = evaluate(PKGBUILD);
+    let e = variables.get("_date").unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "Failed to evaluate _date: Command substitution is not allowed"
+    );
+    let e = evaluate("pkgver=`git describe`\npkgrel=1\n")
+        .full_version()
+        .unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "Failed to evaluate pkgver: Command substitution is not allowed"
+    );
+}
+
+fn parse(text: &str) -> arch_wanko_cc::pkgbuild::Pkgbuild {
+    text.parse().unwrap()
+}
+
+#[test]
+fn functions() {
+    let pkgbuild = parse(
+        r#"pkgver=1.0
+pkgrel=1
+prepare() {
+  pkgver=2.0
+  (cd src && pkgrel=3)
+}
+package_foo()
+{
+  pkgver=$(
+    echo 4.0
+  )
+}
+function check {
+  pkgrel=5
+}
+_after=yes
+"#,
+    );
+    assert_eq!(pkgbuild.get("pkgver").unwrap(), "1.0");
+    assert_eq!(pkgbuild.get("pkgrel").unwrap(), "1");
+    assert_eq!(pkgbuild.get("_after").unwrap(), "yes");
+}
+
+#[test]
+fn braces_as_arguments() {
+    let pkgbuild = parse(
+        r#"pkgver=1.0
+prepare() {
+  echo }
+  echo { a
+  _files=(a } b)
+  _empty=(})
+  sed -i 's/x/y/' {a,b}.c
+}
+echo } fi done
+pkgrel=1
+"#,
+    );
+    assert_eq!(pkgbuild.get("pkgver").unwrap(), "1.0");
+    assert_eq!(pkgbuild.get("pkgrel").unwrap(), "1");
+    assert!(pkgbuild.get("_files").is_none());
+}
+
+#[test]
+fn commands() {
+    let pkgbuild = parse(
+        r#"pkgver=1.0
+build() { make; }
+package_foo()
+{
+  make install
+}
+function check {
+  make check
+}
+if [[ $CARCH == x86_64 ]]; then
+  source+=(x86_64.patch)
+fi
+pkgrel=1
+"#,
+    );
+    assert_eq!(
+        pkgbuild.commands().collect::<Vec<_>>(),
+        vec!["if [[ $CARCH == x86_64 ]]; then\n  source+=(x86_64.patch)\nfi"]
+    );
+}
+
+#[test]
+fn case() {
+    let pkgbuild = parse(
+        r#"pkgver=1.0
+build() { case "$CARCH" in x86_64) _arch=amd64 ;; (i686) _arch=386 ;; esac; pkgver=9.9; }
+case "$CARCH" in
+  x86_64)
+    pkgrel=7
+    ;;
+  *) pkgrel=8 ;;
+esac
+pkgrel=1
+"#,
+    );
+    assert_eq!(pkgbuild.get("pkgver").unwrap(), "1.0");
+    assert_eq!(pkgbuild.get("pkgrel").unwrap(), "1");
+    assert!(pkgbuild.get("_arch").is_none());
+}
+
+#[test]
+fn if_and_loops() {
+    let pkgbuild = parse(
+        r#"pkgver=1.0
+pkgrel=1
+if [[ $CARCH == x86_64 ]]; then pkgrel=5; elif true; then pkgrel=6; else pkgrel=7; fi
+for _f in a b; do
+  pkgver=2.0
+done
+while false; do pkgver=3.0; done
+until true; do
+  if true; then
+    pkgver=4.0
+  fi
+done
+"#,
+    );
+    assert_eq!(pkgbuild.get("pkgver").unwrap(), "1.0");
+    assert_eq!(pkgbuild.get("pkgrel").unwrap(), "1");
+}
+
+#[test]
+fn unterminated_block() {
+    let e = "pkgver=1.0\nif true; then\n  pkgver=2.0\n"
+        .parse::<arch_wanko_cc::pkgbuild::Pkgbuild>()
+        .unwrap_err();
+    assert!(e.to_string().contains("Missing fi"), "{}", e);
+}
+
+#[test]
+fn heredocs() {
+    let pkgbuild = parse(
+        "pkgver=1.0
+package() {
+  cat > foo.sh <<EOF
+pkgver=2.0
+}
+EOF
+  cat <<-'END'
+\tpkgrel=3
+\tEND
+}
+pkgrel=1
+",
+    );
+    assert_eq!(pkgbuild.get("pkgver").unwrap(), "1.0");
+    assert_eq!(pkgbuild.get("pkgrel").unwrap(), "1");
+}
+
+#[test]
+fn comments() {
+    let pkgbuild = parse(
+        r#"# pkgver=0.1
+pkgver=1.0 # pkgver=0.2
+pkgrel=1#2
+source=(a # b
+        c)
+"#,
+    );
+    assert_eq!(pkgbuild.get("pkgver").unwrap(), "1.0");
+    assert_eq!(pkgbuild.get("pkgrel").unwrap(), "1#2");
+    assert_eq!(pkgbuild.get_array("source").unwrap(), vec!["a", "c"]);
+}
+
+#[test]
+fn quoting_styles() {
+    let mut pkgbuild = parse(
+        r#"_bare=a\ b
+_single='a "b"'
+_double="a 'b' \"c\""
+_mixed=a'b'"c"
+_array=(bare 'single' "double")
+"#,
+    );
+    assert_eq!(pkgbuild.get("_bare").unwrap(), "a b");
+    assert_eq!(pkgbuild.get("_single").unwrap(), r#"a "b""#);
+    assert_eq!(pkgbuild.get("_double").unwrap(), r#"a 'b' "c""#);
+    assert_eq!(pkgbuild.get("_mixed").unwrap(), "abc");
+    assert_eq!(
+        pkgbuild.get_array("_array").unwrap(),
+        vec!["bare", "single", "double"]
+    );
+
+    pkgbuild.set("_bare", "x y").unwrap();
+    pkgbuild.set("_single", "x'y").unwrap();
+    pkgbuild.set("_double", "x\"$y").unwrap();
+    pkgbuild.set_element("_array", 0, "x").unwrap();
+    pkgbuild.set_element("_array", 1, "y").unwrap();
+    pkgbuild.set_element("_array", 2, "z").unwrap();
+    assert!(pkgbuild.to_string().contains("_array=(x 'y' \"z\")"));
+    let reparsed = parse(&pkgbuild.to_string());
+    assert_eq!(reparsed.get("_bare").unwrap(), "x y");
+    assert_eq!(reparsed.get("_single").unwrap(), "x'y");
+    assert_eq!(reparsed.get("_double").unwrap(), "x\"$y");
+    assert_eq!(reparsed.get_array("_array").unwrap(), vec!["x", "y", "z"]);
+}
+
+#[test]
+fn trailing_backslash() {
+    for text in &[
+        "pkgver=1.0\npkgrel=1\nfoo=\\",
+        "pkgver=1.0\npkgrel=1\nfoo=\"\\",
+        "foo=\\\u{3042}\npkgver=1.0\npkgrel=1\n",
+    ] {
+        match text.parse::<arch_wanko_cc::pkgbuild::Pkgbuild>() {
+            Ok(pkgbuild) => assert_eq!(pkgbuild.get("pkgver").unwrap(), "1.0"),
+            Err(e) => assert!(e.to_string().contains("Unterminated"), "{}", e),
+        }
+    }
+    assert_eq!(
+        evaluate("pkgver=1.0\npkgrel=1\nfoo=\\")
+            .full_version()
+            .unwrap(),
+        "1.0-1"
+    );
+}
+
+#[test]
+fn append() {
+    let text = r#"pkgname=foo
+pkgver=1.0
+pkgver+=.1
+source=(a.tar.gz)
+source+=(b.patch "c-$pkgver.patch")
+depends=(glibc)
+depends+=-2.32
+options+=(!strip)
+pkgrel=1
+"#;
+    let variables = evaluate(text);
+    assert_eq!(variables.get("pkgver").unwrap(), "1.0.1");
+    assert_eq!(
+        variables.get_array("source").unwrap(),
+        vec!["a.tar.gz", "b.patch", "c-1.0.1.patch"]
+    );
+    assert_eq!(variables.get_array("depends").unwrap(), vec!["glibc-2.32"]);
+    assert_eq!(variables.get_array("options").unwrap(), vec!["!strip"]);
+
+    // Rewriting goes to the assignment which appends are made to
+    let mut pkgbuild = parse(text);
+    assert_eq!(pkgbuild.get("pkgver").unwrap(), "1.0");
+    pkgbuild.set("pkgver", "2.0").unwrap();
+    pkgbuild.set_element("source", 0, "x.tar.gz").unwrap();
+    assert!(pkgbuild
+        .to_string()
+        .starts_with("pkgname=foo\npkgver=2.0\npkgver+=.1\nsource=(x.tar.gz)\n"));
+
+    let e = evaluate("source=(a.tar.gz)\nsource+=(\"$(date).patch\")\n")
+        .get_array("source")
+        .unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "Failed to evaluate source: Command substitution is not allowed"
+    );
+}
+
+#[test]
+fn ansi_c_quoting() {
+    let variables = evaluate(
+        r#"_tab=$'a\tb'
+_quote=$'it\'s'
+_escapes=$'\x41\101\u3042\e\\'
+_unknown=$'\q'
+_double="$'a'"
+_array=($'x\ny' z)
+"#,
+    );
+    assert_eq!(variables.get("_tab").unwrap(), "a\tb");
+    assert_eq!(variables.get("_quote").unwrap(), "it's");
+    assert_eq!(variables.get("_escapes").unwrap(), "AA\u{3042}\x1b\\");
+    assert_eq!(variables.get("_unknown").unwrap(), "\\q");
+    assert_eq!(variables.get("_double").unwrap(), "$'a'");
+    assert_eq!(variables.get_array("_array").unwrap(), vec!["x\ny", "z"]);
+
+    let e = evaluate("_nul=$'\\0'\n").get("_nul").unwrap_err();
+    assert!(
+        e.to_string().starts_with("Failed to evaluate _nul:"),
+        "{}",
+        e
+    );
+}
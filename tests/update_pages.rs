@@ -0,0 +1,120 @@
+//! Runs update-pages against local mirrors of the buckets given by `--local-dir`.
+
+mod common;
+
+/// Creates repositories named `repo-<bucket>` in buckets `<bucket>.example.com`, each having one
+/// package in its database, and returns the root and the directory of the mirrors.
+fn setup(test: &str, buckets: &[&str]) -> (std::path::PathBuf, std::path::PathBuf) {
+    let root = common::temp_dir(test);
+    std::os::unix::fs::symlink(
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("templates"),
+        root.join("templates"),
+    )
+    .unwrap();
+    let mirror = root.join("mirror");
+    for bucket in buckets {
+        let name = format!("repo-{}", bucket);
+        let bucket = format!("{}.example.com", bucket);
+        std::fs::create_dir_all(root.join(&name)).unwrap();
+        std::fs::write(
+            root.join(&name).join(".guzuta.yml"),
+            format!(
+                "name: {}\nbuilds:\n  x86_64:\n    chroot: ../x86_64-root\ns3:\n  bucket: {}\n  region: ap-northeast-1\n  url: https://{}/\n",
+                name, bucket, bucket
+            ),
+        )
+        .unwrap();
+        let db_dir = mirror.join(&bucket).join(&name).join("os/x86_64");
+        std::fs::create_dir_all(&db_dir).unwrap();
+        let desc = "%FILENAME%\nnkf-2.1.5-1-x86_64.pkg.tar.zst\n\n%NAME%\nnkf\n\n%VERSION%\n2.1.5-1\n\n%BUILDDATE%\n1605000000\n\n";
+        std::fs::write(
+            db_dir.join(format!("{}.db", name)),
+            common::tarball("nkf-2.1.5-1", &[("desc", desc)]),
+        )
+        .unwrap();
+    }
+    (root, mirror)
+}
+
+fn run(root: &std::path::Path, mirror: &std::path::Path, args: &[&str]) -> std::process::Output {
+    std::process::Command::new(env!("CARGO_BIN_EXE_update-pages"))
+        .current_dir(root)
+        .arg("--local-dir")
+        .arg(mirror)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn update_pages(root: &std::path::Path, mirror: &std::path::Path, args: &[&str]) -> String {
+    let output = run(root, mirror, args);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn dry_run_buckets() {
+    let (root, mirror) = setup("dry_run_buckets", &["a", "b"]);
+
+    // 3 pages of the site and 6 of the repository in each bucket
+    let stdout = update_pages(&root, &mirror, &["--dry-run"]);
+    assert!(
+        stdout.ends_with("changed: 18, unchanged: 0, failed: 0\n"),
+        "{}",
+        stdout
+    );
+    for bucket in &["a.example.com", "b.example.com"] {
+        for key in &["index.html", "index.json", "feed.atom"] {
+            assert!(
+                stdout.contains(&format!("diff s3://{}/{}\n--- /dev/null\n", bucket, key)),
+                "{}",
+                stdout
+            );
+        }
+    }
+    assert!(!mirror.join("a.example.com/index.html").exists());
+
+    let stdout = update_pages(&root, &mirror, &[]);
+    assert_eq!(stdout, "uploaded: 18, skipped: 0, failed: 0\n");
+    let stdout = update_pages(&root, &mirror, &[]);
+    assert_eq!(stdout, "uploaded: 0, skipped: 18, failed: 0\n");
+}
+
+#[test]
+fn site_url() {
+    let (root, mirror) = setup("site_url", &["a"]);
+
+    update_pages(&root, &mirror, &[]);
+    let index = std::fs::read_to_string(mirror.join("a.example.com/repo-a/index.html")).unwrap();
+    assert!(
+        index.contains("Server = https://a.example.com/$repo/os/$arch"),
+        "{}",
+        index
+    );
+    let feed = std::fs::read_to_string(mirror.join("a.example.com/feed.atom")).unwrap();
+    assert!(
+        feed.contains("https://a.example.com/repo-a/packages/nkf/"),
+        "{}",
+        feed
+    );
+
+    let config = root.join("repo-a/.guzuta.yml");
+    let content = std::fs::read_to_string(&config).unwrap();
+    std::fs::write(
+        &config,
+        content.replace("  url: https://a.example.com/\n", ""),
+    )
+    .unwrap();
+    let output = run(&root, &mirror, &[]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("url is missing in the s3 section of ./repo-a/.guzuta.yml"),
+        "{}",
+        stderr
+    );
+}
@@ -0,0 +1,1061 @@
+//! Runs update-upstream against a local HTTP server which serves the fixtures in place of GitHub.
+
+mod common;
+
+use common::{sha256, tarball, Response, Routes, Server};
+use std::io::Write as _;
+
+const RUBY_PKGBUILD: &str = r#"pkgname='ruby-trunk'
+_commit=fa7484019d4321f8c0775a4811574dccd29bcaea
+_shortcommit=fa7484019d
+pkgver=20201103
+pkgrel=2
+source=("ruby-${_commit}.tar.gz::https://github.com/ruby/ruby/archive/${_commit}.tar.gz"
+        'gemrc')
+sha256sums=('0000000000000000000000000000000000000000000000000000000000000000'
+            '1111111111111111111111111111111111111111111111111111111111111111')
+
+package() {
+  cd ruby-${_commit}
+}
+"#;
+
+const RUBY_UPSTREAM: &str = r#"owner: ruby
+repo: ruby
+strategy:
+  type: head-commit
+version: '{{ commit_date }}'
+source: 'ruby-{{ sha }}.tar.gz'
+variables:
+  _commit: '{{ sha }}'
+  _shortcommit: '{{ short_sha }}'
+  pkgver: '{{ pkgver }}'
+"#;
+
+const VIM_PKGBUILD: &str = r#"pkgname=vim-latest
+_baseversion=8.2
+_patchlevel=1968
+pkgver=${_baseversion}.${_patchlevel}
+pkgrel=3
+source=(vim-$pkgver.tar.gz::https://github.com/vim/vim/archive/v$pkgver.tar.gz)
+sha256sums=('0000000000000000000000000000000000000000000000000000000000000000')
+"#;
+
+const VIM_UPSTREAM: &str = r#"owner: vim
+repo: vim
+strategy:
+  type: latest-tag
+  pattern: '\Av(?P<baseversion>\d+\.\d+)\.(?P<patchlevel>\d+)\z'
+version: '{{ baseversion }}.{{ patchlevel }}'
+source: 'vim-{{ pkgver }}.tar.gz'
+variables:
+  _baseversion: '{{ baseversion }}'
+  _patchlevel: '{{ patchlevel }}'
+"#;
+
+/// Serves the ruby commits whose head is 0123456789abcdef0123456789abcdef01234567.
+fn ruby_commits() -> Routes {
+    Routes::new().route(
+        "/repos/ruby/ruby/commits",
+        Response::fixture("ruby/commits.json"),
+    )
+}
+
+/// Creates a guzuta repository having one package in a fresh directory.
+fn setup(test: &str, name: &str, pkgbuild: &str, upstream: &str) -> std::path::PathBuf {
+    let root = common::temp_dir(test);
+    let package_dir = root.join(name).join("PKGBUILDs").join(name);
+    std::fs::create_dir_all(&package_dir).unwrap();
+    std::fs::write(
+        root.join(name).join(".guzuta.yml"),
+        format!(
+            "name: {}\nsrcdest: sources\nlogdest: logs\npkgbuild: PKGBUILDs\nbuilds:\n  x86_64:\n    chroot: ../x86_64-root\n",
+            name
+        ),
+    )
+    .unwrap();
+    std::fs::write(package_dir.join("PKGBUILD"), pkgbuild).unwrap();
+    std::fs::write(package_dir.join("upstream.yml"), upstream).unwrap();
+    root
+}
+
+fn update_upstream(root: &std::path::Path, server: &Server) -> std::process::Output {
+    std::process::Command::new(env!("CARGO_BIN_EXE_update-upstream"))
+        .current_dir(root)
+        .env_remove("GITHUB_TOKEN")
+        .arg("--api-url")
+        .arg(server.url())
+        .arg("--web-url")
+        .arg(server.url())
+        .arg("--cache-file")
+        .arg(root.join("github-cache.json"))
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn head_commit() {
+    let archive = tarball(
+        "ruby-0123456789abcdef0123456789abcdef01234567",
+        &[("README.md", "# Ruby\n")],
+    );
+    let checksum = sha256(&archive);
+    let server = Server::start(|_| {
+        ruby_commits().archive(
+            "ruby",
+            "ruby",
+            "0123456789abcdef0123456789abcdef01234567",
+            &archive,
+        )
+    });
+    let root = setup("head_commit", "ruby-trunk", RUBY_PKGBUILD, RUBY_UPSTREAM);
+
+    let output = update_upstream(&root, &server);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let pkgbuild =
+        std::fs::read_to_string(root.join("ruby-trunk/PKGBUILDs/ruby-trunk/PKGBUILD")).unwrap();
+    assert_eq!(
+        pkgbuild,
+        format!(
+            r#"pkgname='ruby-trunk'
+_commit=0123456789abcdef0123456789abcdef01234567
+_shortcommit=0123456789
+pkgver=20201120
+pkgrel=1
+source=("ruby-${{_commit}}.tar.gz::https://github.com/ruby/ruby/archive/${{_commit}}.tar.gz"
+        'gemrc')
+sha256sums=('{}'
+            '1111111111111111111111111111111111111111111111111111111111111111')
+
+package() {{
+  cd ruby-${{_commit}}
+}}
+"#,
+            checksum
+        )
+    );
+    let source = std::fs::read(
+        root.join("ruby-trunk/sources/ruby-0123456789abcdef0123456789abcdef01234567.tar.gz"),
+    )
+    .unwrap();
+    assert_eq!(sha256(&source), checksum);
+}
+
+#[test]
+fn bump_pkgrel() {
+    // Another commit of the same day keeps pkgver, so pkgrel is bumped instead of reset
+    let archive = tarball(
+        "ruby-0123456789abcdef0123456789abcdef01234567",
+        &[("README.md", "# Ruby\n")],
+    );
+    let server = Server::start(|_| {
+        ruby_commits().archive(
+            "ruby",
+            "ruby",
+            "0123456789abcdef0123456789abcdef01234567",
+            &archive,
+        )
+    });
+    let pkgbuild =
+        RUBY_PKGBUILD.replace("pkgver=20201103\npkgrel=2\n", "pkgver=20201120\npkgrel=1\n");
+    let root = setup("bump_pkgrel", "ruby-trunk", &pkgbuild, RUBY_UPSTREAM);
+
+    let output = update_upstream(&root, &server);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let pkgbuild =
+        std::fs::read_to_string(root.join("ruby-trunk/PKGBUILDs/ruby-trunk/PKGBUILD")).unwrap();
+    assert!(pkgbuild.contains(
+        "_commit=0123456789abcdef0123456789abcdef01234567\n_shortcommit=0123456789\npkgver=20201120\npkgrel=2\n"
+    ));
+}
+
+#[test]
+fn latest_tag() {
+    let archive = tarball("vim-8.10.0001", &[("README.md", "# Vim\n")]);
+    let checksum = sha256(&archive);
+    let server = Server::start(|url| {
+        let first_page = Response::fixture("vim/tags-1.json").header(
+            "Link",
+            format!(
+                r#"<{}/repos/vim/vim/git/refs/tags?per_page=100&page=2>; rel="next", <{}/repos/vim/vim/git/refs/tags?per_page=100&page=2>; rel="last""#,
+                url, url
+            ),
+        );
+        Routes::new()
+            .route("/repos/vim/vim/git/refs/tags?per_page=100", first_page)
+            .route(
+                "/repos/vim/vim/git/refs/tags?per_page=100&page=2",
+                Response::fixture("vim/tags-2.json"),
+            )
+            .archive("vim", "vim", "v8.10.0001", &archive)
+    });
+    let root = setup("latest_tag", "vim-latest", VIM_PKGBUILD, VIM_UPSTREAM);
+
+    let output = update_upstream(&root, &server);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Skip tag v8.2.1100-rc"));
+    let pkgbuild =
+        std::fs::read_to_string(root.join("vim-latest/PKGBUILDs/vim-latest/PKGBUILD")).unwrap();
+    assert_eq!(
+        pkgbuild,
+        format!(
+            r#"pkgname=vim-latest
+_baseversion=8.10
+_patchlevel=0001
+pkgver=${{_baseversion}}.${{_patchlevel}}
+pkgrel=1
+source=(vim-$pkgver.tar.gz::https://github.com/vim/vim/archive/v$pkgver.tar.gz)
+sha256sums=('{}')
+"#,
+            checksum
+        )
+    );
+    let source = std::fs::read(root.join("vim-latest/sources/vim-8.10.0001.tar.gz")).unwrap();
+    assert_eq!(sha256(&source), checksum);
+}
+
+#[test]
+fn up_to_date() {
+    let server = Server::start(|_| ruby_commits());
+    let pkgbuild = RUBY_PKGBUILD
+        .replace(
+            "fa7484019d4321f8c0775a4811574dccd29bcaea",
+            "0123456789abcdef0123456789abcdef01234567",
+        )
+        .replace("fa7484019d", "0123456789")
+        .replace("20201103", "20201120");
+    let root = setup("up_to_date", "ruby-trunk", &pkgbuild, RUBY_UPSTREAM);
+
+    let output = update_upstream(&root, &server);
+    assert_eq!(
+        output.status.code(),
+        Some(2),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        std::fs::read_to_string(root.join("ruby-trunk/PKGBUILDs/ruby-trunk/PKGBUILD")).unwrap(),
+        pkgbuild
+    );
+    assert_eq!(server.requests(), vec!["/repos/ruby/ruby/commits"]);
+}
+
+#[test]
+fn github_cache() {
+    let server = Server::start(|_| {
+        Routes::new().route(
+            "/repos/ruby/ruby/commits",
+            Response::fixture("ruby/commits.json").header("ETag", "\"commits\"".to_owned()),
+        )
+    });
+    let pkgbuild = RUBY_PKGBUILD
+        .replace(
+            "fa7484019d4321f8c0775a4811574dccd29bcaea",
+            "0123456789abcdef0123456789abcdef01234567",
+        )
+        .replace("fa7484019d", "0123456789")
+        .replace("20201103", "20201120");
+    let root = setup("github_cache", "ruby-trunk", &pkgbuild, RUBY_UPSTREAM);
+    let cache_path = root.join("github-cache.json");
+    std::fs::write(
+        &cache_path,
+        serde_json::json!({
+            "http://127.0.0.1:1/repos/ruby/ruby/tags": {"etag": "\"tags\"", "next": null, "body": "[]"},
+        })
+        .to_string(),
+    )
+    .unwrap();
+
+    let output = update_upstream(&root, &server);
+    assert_eq!(
+        output.status.code(),
+        Some(2),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    // Only the responses used in the last run are kept
+    let cache: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_slice(&std::fs::read(&cache_path).unwrap()).unwrap();
+    assert_eq!(
+        cache.keys().collect::<Vec<_>>(),
+        vec![&format!("{}/repos/ruby/ruby/commits", server.url())]
+    );
+    assert_eq!(cache.values().next().unwrap()["etag"], "\"commits\"");
+}
+
+#[test]
+fn reuse_known_source() {
+    let archive = tarball(
+        "ruby-0123456789abcdef0123456789abcdef01234567",
+        &[("README.md", "# Ruby\n")],
+    );
+    let checksum = sha256(&archive);
+    let server = Server::start(|_| ruby_commits());
+    let root = setup(
+        "reuse_known_source",
+        "ruby-trunk",
+        RUBY_PKGBUILD,
+        RUBY_UPSTREAM,
+    );
+    let srcdest = root.join("ruby-trunk/sources");
+    std::fs::create_dir_all(&srcdest).unwrap();
+    std::fs::write(
+        srcdest.join("ruby-0123456789abcdef0123456789abcdef01234567.tar.gz"),
+        &archive,
+    )
+    .unwrap();
+    std::fs::write(
+        srcdest.join("SHA256SUMS"),
+        format!(
+            "{}  ruby-0123456789abcdef0123456789abcdef01234567.tar.gz\n",
+            checksum
+        ),
+    )
+    .unwrap();
+
+    let output = update_upstream(&root, &server);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let pkgbuild =
+        std::fs::read_to_string(root.join("ruby-trunk/PKGBUILDs/ruby-trunk/PKGBUILD")).unwrap();
+    assert!(pkgbuild.contains(&format!("sha256sums=('{}'", checksum)));
+    assert_eq!(server.requests(), vec!["/repos/ruby/ruby/commits"]);
+}
+
+fn prune_sources(root: &std::path::Path) -> std::process::Output {
+    std::process::Command::new(env!("CARGO_BIN_EXE_update-upstream"))
+        .current_dir(root)
+        .arg("--api-url")
+        .arg("http://127.0.0.1:1")
+        .arg("--prune")
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn prune() {
+    let pkgbuild = RUBY_PKGBUILD.replace(
+        "        'gemrc')\n",
+        "        'gemrc')\nsource+=(\"fix-${_shortcommit}.patch\")\nsource_x86_64=('x86_64.patch')\n",
+    );
+    let root = setup("prune", "ruby-trunk", &pkgbuild, RUBY_UPSTREAM);
+    let srcdest = root.join("ruby-trunk/sources");
+    std::fs::create_dir_all(&srcdest).unwrap();
+    let current = "ruby-fa7484019d4321f8c0775a4811574dccd29bcaea.tar.gz";
+    let old = "ruby-0123456789abcdef0123456789abcdef01234567.tar.gz";
+    std::fs::write(srcdest.join(current), "current").unwrap();
+    std::fs::write(srcdest.join(old), "old").unwrap();
+    std::fs::write(srcdest.join("fix-fa7484019d.patch"), "appended").unwrap();
+    std::fs::write(srcdest.join("x86_64.patch"), "x86_64").unwrap();
+    std::fs::write(
+        srcdest.join("SHA256SUMS"),
+        format!(
+            "{}  {}\n{}  {}\n",
+            sha256(b"old"),
+            old,
+            sha256(b"current"),
+            current
+        ),
+    )
+    .unwrap();
+
+    let output = prune_sources(&root);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(srcdest.join(current).is_file());
+    assert!(srcdest.join("fix-fa7484019d.patch").is_file());
+    assert!(srcdest.join("x86_64.patch").is_file());
+    assert!(!srcdest.join(old).exists());
+    assert_eq!(
+        std::fs::read_to_string(srcdest.join("SHA256SUMS")).unwrap(),
+        format!("{}  {}\n", sha256(b"current"), current)
+    );
+    assert!(String::from_utf8_lossy(&output.stdout)
+        .contains("ruby-trunk: removed 1 files (3 B), kept 4 files"));
+}
+
+#[test]
+fn prune_unevaluable() {
+    for (test, line, message) in &[
+        (
+            "prune_conditional",
+            "if [[ $CARCH == x86_64 ]]; then source+=(x86_64.patch); fi\n",
+            "whose top-level command may assign sources: if [[ $CARCH == x86_64 ]]",
+        ),
+        (
+            "prune_command_substitution",
+            "source_x86_64=(\"$(uname -m).patch\")\n",
+            "Cannot expand source_x86_64",
+        ),
+    ] {
+        let pkgbuild = RUBY_PKGBUILD.replace("package() {", &format!("{}package() {{", line));
+        let root = setup(test, "ruby-trunk", &pkgbuild, RUBY_UPSTREAM);
+        let srcdest = root.join("ruby-trunk/sources");
+        std::fs::create_dir_all(&srcdest).unwrap();
+        std::fs::write(srcdest.join("x86_64.patch"), "x86_64").unwrap();
+
+        let output = prune_sources(&root);
+        assert_eq!(output.status.code(), Some(1));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(message), "{}", stderr);
+        assert!(srcdest.join("x86_64.patch").is_file());
+    }
+}
+
+#[test]
+fn truncated_download() {
+    let archive = tarball(
+        "ruby-0123456789abcdef0123456789abcdef01234567",
+        &[("README.md", "# Ruby\n")],
+    );
+    let server = Server::start(|_| {
+        ruby_commits().route(
+            "/ruby/ruby/archive/0123456789abcdef0123456789abcdef01234567.tar.gz",
+            Response::new(archive.clone())
+                .header("Content-Length", (archive.len() + 100).to_string()),
+        )
+    });
+    let root = setup(
+        "truncated_download",
+        "ruby-trunk",
+        RUBY_PKGBUILD,
+        RUBY_UPSTREAM,
+    );
+
+    let output = update_upstream(&root, &server);
+    assert_eq!(output.status.code(), Some(1));
+    // hyper stops reading at the end of the connection before download() checks the size itself
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(
+            "Failed to download ./ruby-trunk/sources/ruby-0123456789abcdef0123456789abcdef01234567.tar.gz"
+        ),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("end of file before message length reached"),
+        "{}",
+        stderr
+    );
+    assert_eq!(
+        std::fs::read_to_string(root.join("ruby-trunk/PKGBUILDs/ruby-trunk/PKGBUILD")).unwrap(),
+        RUBY_PKGBUILD
+    );
+    let srcdest = root.join("ruby-trunk/sources");
+    let mut files: Vec<_> = std::fs::read_dir(&srcdest)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    files.sort();
+    assert!(files.is_empty(), "{:?}", files);
+}
+
+#[test]
+fn defines() {
+    let archive = tarball(
+        "ruby-0123456789abcdef0123456789abcdef01234567",
+        &[
+            (
+                "include/ruby/version.h",
+                "#define RUBY_API_VERSION_MAJOR 3\n#define RUBY_API_VERSION_MINOR 1 /* minor */\n#define RUBY_API_VERSION_TEENY 0\n#define RUBY_API_VERSION_CODE (RUBY_API_VERSION_MAJOR*10000)\n",
+            ),
+            (
+                "version.h",
+                "# define RUBY_VERSION_MAJOR RUBY_API_VERSION_MAJOR\n#define RUBY_VERSION_TEENY 0\n#define RUBY_RELEASE_YEAR 2020\n#define RUBY_PATCHLEVEL_STR \"dev\"\n",
+            ),
+        ],
+    );
+    let server = Server::start(|_| {
+        ruby_commits().archive(
+            "ruby",
+            "ruby",
+            "0123456789abcdef0123456789abcdef01234567",
+            &archive,
+        )
+    });
+    let pkgbuild = RUBY_PKGBUILD.replace(
+        "pkgver=20201103\n",
+        "pkgver=20201103\n_rubyver=3.0.0\nprovides=(\"ruby=${_rubyver}\" 'rubygems')\n",
+    );
+    let upstream = format!(
+        "{}  _rubyver: '{{{{ RUBY_VERSION_MAJOR }}}}.{{{{ RUBY_API_VERSION_MINOR }}}}.{{{{ RUBY_VERSION_TEENY }}}}-{{{{ RUBY_PATCHLEVEL_STR }}}}'\ndefines:\n  - include/ruby/version.h\n  - version.h\n",
+        RUBY_UPSTREAM
+    );
+    let root = setup("defines", "ruby-trunk", &pkgbuild, &upstream);
+
+    let output = update_upstream(&root, &server);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let pkgbuild =
+        std::fs::read_to_string(root.join("ruby-trunk/PKGBUILDs/ruby-trunk/PKGBUILD")).unwrap();
+    assert!(pkgbuild.contains("_commit=0123456789abcdef0123456789abcdef01234567\n"));
+    assert!(pkgbuild.contains("\n_rubyver=3.1.0-dev\nprovides=(\"ruby=${_rubyver}\" 'rubygems')\n"));
+}
+
+#[test]
+fn defines_render_error() {
+    let server = Server::start(|_| ruby_commits());
+    let upstream = format!(
+        "{}  _rubyver: '{{{{ RUBY_API_VERSION_MAJOR }}}}.{{{{ short_sha '\ndefines:\n  - version.h\n",
+        RUBY_UPSTREAM
+    );
+    let root = setup(
+        "defines_render_error",
+        "ruby-trunk",
+        RUBY_PKGBUILD,
+        &upstream,
+    );
+
+    let output = update_upstream(&root, &server);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(!server
+        .requests()
+        .iter()
+        .any(|path| path.starts_with("/ruby/ruby/archive/")));
+}
+
+/// Signs `payload` with a throwaway key and returns the armored signature and the public keyring.
+fn sign(test: &str, payload: &str) -> (String, Vec<u8>) {
+    // Kept short because gpg-agent puts its socket in GNUPGHOME
+    let home = std::env::temp_dir().join(format!("gnupg-{}-{}", test, std::process::id()));
+    if home.exists() {
+        std::fs::remove_dir_all(&home).unwrap();
+    }
+    std::fs::create_dir_all(&home).unwrap();
+    let gpg = |args: &[&str], stdin: &str| {
+        let mut child = std::process::Command::new("gpg")
+            .env("GNUPGHOME", &home)
+            .arg("--batch")
+            .arg("--passphrase")
+            .arg("")
+            .args(args)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(stdin.as_bytes())
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success());
+        output.stdout
+    };
+    gpg(
+        &[
+            "--quick-gen-key",
+            "Test <test@example.com>",
+            "ed25519",
+            "sign",
+            "never",
+        ],
+        "",
+    );
+    let signature = gpg(&["--detach-sign", "--armor"], payload);
+    let keyring = gpg(&["--export"], "");
+    let _ = std::process::Command::new("gpgconf")
+        .env("GNUPGHOME", &home)
+        .arg("--kill")
+        .arg("gpg-agent")
+        .status();
+    let _ = std::fs::remove_dir_all(&home);
+    (String::from_utf8(signature).unwrap(), keyring)
+}
+
+/// Serves vim with the annotated tag v8.2.2000, whose signature is made over `signed` and whose
+/// payload is returned as `payload`. The tag object is `tag_sha`, or the hash of the payload and
+/// the signature like git when not given. The tag ref resolves to `moved_to` instead of the tag
+/// object when given, as if the tag were moved after listing the tags.
+fn verify_tag_setup(
+    test: &str,
+    signed: &str,
+    payload: &str,
+    tag_sha: Option<&str>,
+    moved_to: Option<&str>,
+) -> (Server, std::path::PathBuf) {
+    use sha1::Digest as _;
+
+    let (signature, keyring) = sign(test, signed);
+    let tag_sha = tag_sha.map(ToOwned::to_owned).unwrap_or_else(|| {
+        let object = format!("{}{}", payload, signature);
+        sha1::Sha1::digest(format!("tag {}\0{}", object.len(), object).as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    });
+    let refs = serde_json::json!([
+        {
+            "ref": "refs/tags/v8.2.1999",
+            "object": {"sha": "7777777777777777777777777777777777777777", "type": "tag"},
+        },
+        {
+            "ref": "refs/tags/v8.2.2000",
+            "object": {"sha": tag_sha, "type": "tag"},
+        },
+    ]);
+    let tag = serde_json::json!({
+        "sha": tag_sha,
+        "tag": "v8.2.2000",
+        "verification": {
+            "verified": true,
+            "reason": "valid",
+            "signature": signature,
+            "payload": payload,
+        },
+    });
+    let tag_ref = serde_json::json!({
+        "ref": "refs/tags/v8.2.2000",
+        "object": {"sha": moved_to.unwrap_or(&tag_sha), "type": "tag"},
+    });
+    let archive = tarball("vim-8.2.2000", &[("README.md", "# Vim\n")]);
+    let server = Server::start(|_| {
+        Routes::new()
+            .route(
+                "/repos/vim/vim/git/refs/tags?per_page=100",
+                Response::json(&refs),
+            )
+            .route(
+                "/repos/vim/vim/git/ref/tags/v8.2.2000",
+                Response::json(&tag_ref),
+            )
+            .route(
+                format!("/repos/vim/vim/git/tags/{}", tag_sha),
+                Response::json(&tag),
+            )
+            .archive("vim", "vim", "v8.2.2000", &archive)
+    });
+    let pkgbuild = VIM_PKGBUILD.replace(
+        "_patchlevel=1968\n",
+        "_patchlevel=1968\n_tagobject=0000000000000000000000000000000000000000\n",
+    );
+    let upstream = format!(
+        "{}  _tagobject: '{{{{ tag_sha }}}}'\nverify_tag:\n  keyring: keyring.gpg\n",
+        VIM_UPSTREAM
+    );
+    let root = setup(test, "vim-latest", &pkgbuild, &upstream);
+    std::fs::write(
+        root.join("vim-latest/PKGBUILDs/vim-latest/keyring.gpg"),
+        keyring,
+    )
+    .unwrap();
+    (server, root)
+}
+
+const TAG_PAYLOAD: &str = "object 2222222222222222222222222222222222222222\ntype commit\ntag v8.2.2000\ntagger Vim <vim@example.com> 1600000000 +0000\n\npatch 8.2.2000\n";
+
+#[test]
+fn verify_tag() {
+    let (server, root) = verify_tag_setup("verify_tag", TAG_PAYLOAD, TAG_PAYLOAD, None, None);
+
+    let output = update_upstream(&root, &server);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let pkgbuild =
+        std::fs::read_to_string(root.join("vim-latest/PKGBUILDs/vim-latest/PKGBUILD")).unwrap();
+    assert!(pkgbuild.contains("\n_patchlevel=2000\n_tagobject="));
+    assert!(!pkgbuild.contains("_tagobject=0000000000000000000000000000000000000000"));
+    // The archive is downloaded by the tag name, which is what the PKGBUILD fetches, and the tag
+    // is checked to still point at the verified object before and after that
+    let requests = server.requests();
+    let archive = requests
+        .iter()
+        .position(|path| path == "/vim/vim/archive/v8.2.2000.tar.gz")
+        .unwrap();
+    let checks: Vec<_> = requests
+        .iter()
+        .enumerate()
+        .filter(|(_, path)| *path == "/repos/vim/vim/git/ref/tags/v8.2.2000")
+        .map(|(i, _)| i)
+        .collect();
+    assert_eq!(checks.len(), 2, "{:?}", requests);
+    assert!(checks[0] < archive && archive < checks[1], "{:?}", requests);
+}
+
+/// Runs update-upstream expecting it to fail with `message` before downloading anything.
+fn assert_verify_tag_failure(server: &Server, root: &std::path::Path, message: &str) {
+    let pkgbuild =
+        std::fs::read_to_string(root.join("vim-latest/PKGBUILDs/vim-latest/PKGBUILD")).unwrap();
+
+    let output = update_upstream(root, server);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(message), "{}", stderr);
+    assert_eq!(
+        std::fs::read_to_string(root.join("vim-latest/PKGBUILDs/vim-latest/PKGBUILD")).unwrap(),
+        pkgbuild
+    );
+    assert!(!server
+        .requests()
+        .iter()
+        .any(|path| path.starts_with("/vim/vim/archive/")));
+}
+
+#[test]
+fn verify_tag_failure() {
+    let (server, root) = verify_tag_setup(
+        "verify_tag_failure",
+        TAG_PAYLOAD,
+        &TAG_PAYLOAD.replace(
+            "2222222222222222222222222222222222222222",
+            "9999999999999999999999999999999999999999",
+        ),
+        None,
+        None,
+    );
+    assert_verify_tag_failure(&server, &root, "Failed to verify tag v8.2.2000");
+}
+
+#[test]
+fn verify_tag_other_tag() {
+    // A genuine signature of another tag
+    let payload = TAG_PAYLOAD.replace("tag v8.2.2000", "tag v8.2.1999");
+    let (server, root) = verify_tag_setup("verify_tag_other_tag", &payload, &payload, None, None);
+    assert_verify_tag_failure(&server, &root, "Tag v8.2.2000 is signed as v8.2.1999");
+}
+
+#[test]
+fn verify_tag_object_mismatch() {
+    let (server, root) = verify_tag_setup(
+        "verify_tag_object_mismatch",
+        TAG_PAYLOAD,
+        TAG_PAYLOAD,
+        Some("8888888888888888888888888888888888888888"),
+        None,
+    );
+    assert_verify_tag_failure(
+        &server,
+        &root,
+        "Tag v8.2.2000 is 8888888888888888888888888888888888888888 but its payload and signature hash to",
+    );
+}
+
+#[test]
+fn verify_tag_moved() {
+    let (server, root) = verify_tag_setup(
+        "verify_tag_moved",
+        TAG_PAYLOAD,
+        TAG_PAYLOAD,
+        None,
+        Some("6666666666666666666666666666666666666666"),
+    );
+    assert_verify_tag_failure(&server, &root, "Tag v8.2.2000 was moved from the verified");
+}
+
+#[test]
+fn required_checks() {
+    let archive = tarball(
+        "ruby-fa7484019d4321f8c0775a4811574dccd29bcaea",
+        &[("README.md", "# Ruby\n")],
+    );
+    let server = Server::start(|_| {
+        ruby_commits()
+            .route(
+                "/repos/ruby/ruby/commits/0123456789abcdef0123456789abcdef01234567/check-runs?check_name=make+check&filter=latest",
+                Response::json(&serde_json::json!({
+                    "total_count": 1,
+                    "check_runs": [{"name": "make check", "status": "completed", "conclusion": "skipped"}],
+                })),
+            )
+            .route(
+                "/repos/ruby/ruby/commits/fa7484019d4321f8c0775a4811574dccd29bcaea/check-runs?check_name=make+check&filter=latest",
+                Response::json(&serde_json::json!({
+                    "total_count": 1,
+                    "check_runs": [{"name": "make check", "status": "completed", "conclusion": "success"}],
+                })),
+            )
+            .route(
+                "/repos/ruby/ruby/commits/fa7484019d4321f8c0775a4811574dccd29bcaea/check-runs?check_name=continuous-integration%2Fappveyor&filter=latest",
+                Response::json(&serde_json::json!({"total_count": 0, "check_runs": []})),
+            )
+            .route(
+                "/repos/ruby/ruby/commits/fa7484019d4321f8c0775a4811574dccd29bcaea/status?per_page=100",
+                Response::json(&serde_json::json!({
+                    "state": "success",
+                    "statuses": [{"context": "continuous-integration/appveyor", "state": "success"}],
+                })),
+            )
+            .archive("ruby", "ruby", "fa7484019d4321f8c0775a4811574dccd29bcaea", &archive)
+    });
+    let pkgbuild = RUBY_PKGBUILD
+        .replace(
+            "fa7484019d4321f8c0775a4811574dccd29bcaea",
+            "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+        )
+        .replace("fa7484019d", "aaaaaaaaaa")
+        .replace("20201103", "20201020");
+    let upstream = RUBY_UPSTREAM.replace(
+        "  type: head-commit\n",
+        "  type: head-commit\n  required_checks:\n    - make check\n    - continuous-integration/appveyor\n",
+    );
+    let root = setup("required_checks", "ruby-trunk", &pkgbuild, &upstream);
+
+    let output = update_upstream(&root, &server);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Skip 0123456789abcdef0123456789abcdef01234567: make check is skipped"));
+    let pkgbuild =
+        std::fs::read_to_string(root.join("ruby-trunk/PKGBUILDs/ruby-trunk/PKGBUILD")).unwrap();
+    assert!(pkgbuild.contains("_commit=fa7484019d4321f8c0775a4811574dccd29bcaea\n_shortcommit=fa7484019d\npkgver=20201103\npkgrel=1\n"));
+}
+
+fn commit_json(sha: &str, date: &str) -> Response {
+    Response::json(&serde_json::json!({
+        "sha": sha,
+        "commit": {"committer": {"name": "git", "date": date}},
+    }))
+}
+
+#[test]
+fn pin_commit() {
+    let archive = tarball(
+        "ruby-fa7484019d4321f8c0775a4811574dccd29bcaea",
+        &[("README.md", "# Ruby\n")],
+    );
+    let server = Server::start(|_| {
+        Routes::new()
+            .route(
+                "/repos/ruby/ruby/commits/fa7484019d",
+                commit_json(
+                    "fa7484019d4321f8c0775a4811574dccd29bcaea",
+                    "2020-11-03T10:00:00Z",
+                ),
+            )
+            .archive(
+                "ruby",
+                "ruby",
+                "fa7484019d4321f8c0775a4811574dccd29bcaea",
+                &archive,
+            )
+    });
+    let pkgbuild = RUBY_PKGBUILD
+        .replace(
+            "fa7484019d4321f8c0775a4811574dccd29bcaea",
+            "0123456789abcdef0123456789abcdef01234567",
+        )
+        .replace("fa7484019d", "0123456789")
+        .replace("20201103", "20201120");
+    let root = setup("pin_commit", "ruby-trunk", &pkgbuild, RUBY_UPSTREAM);
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_update-upstream"))
+        .current_dir(&root)
+        .arg("--api-url")
+        .arg(server.url())
+        .arg("--web-url")
+        .arg(server.url())
+        .arg("--commit")
+        .arg("fa7484019d")
+        .arg("ruby-trunk/PKGBUILDs/ruby-trunk")
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let pkgbuild =
+        std::fs::read_to_string(root.join("ruby-trunk/PKGBUILDs/ruby-trunk/PKGBUILD")).unwrap();
+    assert!(pkgbuild.contains("_commit=fa7484019d4321f8c0775a4811574dccd29bcaea\n_shortcommit=fa7484019d\npkgver=20201103\npkgrel=1\n"));
+}
+
+/// Creates a repository having a linear history of `n` commits, one per day from 2020-11-01.
+fn linear_history(path: &std::path::Path, n: usize) -> Vec<git2::Oid> {
+    let repo = git2::Repository::init(path).unwrap();
+    let mut oids: Vec<git2::Oid> = Vec::new();
+    for i in 0..n {
+        let time = git2::Time::new(1_604_188_800 + 86400 * i as i64, 0);
+        let signature = git2::Signature::new("git", "git@example.com", &time).unwrap();
+        let mut index = repo.index().unwrap();
+        let blob = repo.blob(i.to_string().as_bytes()).unwrap();
+        index
+            .add(&git2::IndexEntry {
+                ctime: git2::IndexTime::new(0, 0),
+                mtime: git2::IndexTime::new(0, 0),
+                dev: 0,
+                ino: 0,
+                mode: 0o100644,
+                uid: 0,
+                gid: 0,
+                file_size: 0,
+                id: blob,
+                flags: 0,
+                flags_extended: 0,
+                path: b"n".to_vec(),
+            })
+            .unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let parents: Vec<_> = oids
+            .last()
+            .map(|oid| repo.find_commit(*oid).unwrap())
+            .into_iter()
+            .collect();
+        let parents: Vec<_> = parents.iter().collect();
+        let oid = repo
+            .commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                &format!("commit {}", i),
+                &tree,
+                &parents,
+            )
+            .unwrap();
+        oids.push(oid);
+    }
+    oids
+}
+
+#[test]
+fn bisect() {
+    let root = setup("bisect", "ruby-trunk", RUBY_PKGBUILD, RUBY_UPSTREAM);
+    let oids = linear_history(&root.join("ruby.git"), 8);
+    let middle = oids[4].to_string();
+    let archive = tarball(&format!("ruby-{}", middle), &[("README.md", "# Ruby\n")]);
+    // The commit date is read from the clone, so the API isn't served
+    let server = Server::start(|_| Routes::new().archive("ruby", "ruby", &middle, &archive));
+    let bisect_upstream = |good: &git2::Oid, bad: &git2::Oid| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_bisect-upstream"))
+            .current_dir(&root)
+            .arg("--api-url")
+            .arg(server.url())
+            .arg("--web-url")
+            .arg(server.url())
+            .arg("--git-dir")
+            .arg("ruby.git")
+            .arg("--good")
+            .arg(good.to_string())
+            .arg("--bad")
+            .arg(bad.to_string())
+            .arg("ruby-trunk/PKGBUILDs/ruby-trunk")
+            .output()
+            .unwrap()
+    };
+
+    let output = bisect_upstream(&oids[0], &oids[7]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).contains(&format!(
+        "Bisecting: 6 commits left to test, testing {}",
+        middle
+    )));
+    let pkgbuild =
+        std::fs::read_to_string(root.join("ruby-trunk/PKGBUILDs/ruby-trunk/PKGBUILD")).unwrap();
+    assert!(pkgbuild.contains(&format!(
+        "_commit={}\n_shortcommit={}\npkgver=20201105\npkgrel=1\n",
+        middle,
+        &middle[..10]
+    )));
+    assert_eq!(
+        server.requests(),
+        vec![format!("/ruby/ruby/archive/{}.tar.gz", middle)]
+    );
+
+    let output = bisect_upstream(&oids[6], &oids[7]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("{} is the first bad commit\n", oids[7])
+    );
+
+    let output = bisect_upstream(&oids[7], &oids[7]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No commits between good and bad"));
+}
+
+#[test]
+fn bisect_merge() {
+    let path = common::temp_dir("bisect_merge.git");
+    let repo = git2::Repository::init_bare(&path).unwrap();
+    let tree = repo
+        .find_tree(repo.treebuilder(None).unwrap().write().unwrap())
+        .unwrap();
+    let mut time = 1_604_188_800;
+    let mut commit = |message: &str, parents: &[git2::Oid]| {
+        time += 86400;
+        let signature =
+            git2::Signature::new("git", "git@example.com", &git2::Time::new(time, 0)).unwrap();
+        let parents: Vec<_> = parents
+            .iter()
+            .map(|oid| repo.find_commit(*oid).unwrap())
+            .collect();
+        let parents: Vec<_> = parents.iter().collect();
+        repo.commit(None, &signature, &signature, message, &tree, &parents)
+            .unwrap()
+    };
+    // base - a1 - a2 - merge
+    //     \             /
+    //      b1 - b2 - b3
+    let base = commit("base", &[]);
+    let a1 = commit("a1", &[base]);
+    let b1 = commit("b1", &[base]);
+    let a2 = commit("a2", &[a1]);
+    let b2 = commit("b2", &[b1]);
+    let b3 = commit("b3", &[b2]);
+    let merge = commit("merge", &[a2, b3]);
+
+    // b2 has 2 of the 6 candidates as ancestors and b3 has 3
+    assert_eq!(
+        arch_wanko_cc::upstream::bisect(&repo, &[base], merge).unwrap(),
+        arch_wanko_cc::upstream::Bisect::Step {
+            commit: b3,
+            remaining: 6
+        }
+    );
+    assert_eq!(
+        arch_wanko_cc::upstream::bisect(&repo, &[b3], merge).unwrap(),
+        arch_wanko_cc::upstream::Bisect::Step {
+            commit: a2,
+            remaining: 3
+        }
+    );
+    assert_eq!(
+        arch_wanko_cc::upstream::bisect(&repo, &[a2, b3], merge).unwrap(),
+        arch_wanko_cc::upstream::Bisect::Found(merge)
+    );
+    for (good, bad) in &[(merge, merge), (merge, b2)] {
+        let e = arch_wanko_cc::upstream::bisect(&repo, &[*good], *bad).unwrap_err();
+        assert!(
+            e.to_string().starts_with("No commits between good and bad"),
+            "{}",
+            e
+        );
+    }
+}